@@ -1,151 +1,35 @@
-use core::sync::atomic::Ordering;
+use crate::{DampingMode, ReverbBank, ToneMode, EQ_MAX, LOG_MID};
 
-use crate::{parameters::{ReverbParameters, FREQUENCY_MAX, FREQUENCY_MIN, REVERB_CURVE}, EQ_MAX, LOG_MID};
+pub const EQ_MID: f64 = EQ_MAX as f64*LOG_MID;
 
-const EQ_MID: f64 = EQ_MAX as f64*LOG_MID;
-
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
-pub struct ReverbBank
+#[cfg(test)]
+#[test]
+fn missing_fields()
 {
-    #[serde(default = "ReverbBank::default_gain")]
-    pub gain: f64,
-    #[serde(default = "ReverbBank::default_wet")]
-    pub wet: f64,
-    #[serde(default = "ReverbBank::default_dry")]
-    pub dry: f64,
-    #[serde(default = "ReverbBank::default_feedback")]
-    pub feedback: f64,
-    #[serde(default = "ReverbBank::default_stereo_separation")]
-    pub stereo_separation: f64,
-    #[serde(default = "ReverbBank::default_ceiling")]
-    pub ceiling: f64,
-    #[serde(default = "ReverbBank::default_floor")]
-    pub floor: f64,
-    #[serde(default = "ReverbBank::default_prescence")]
-    pub prescence: f64,
-    #[serde(default = "ReverbBank::default_mids")]
-    pub mids: f64,
-    #[serde(default = "ReverbBank::default_mud")]
-    pub mud: f64,
-    #[serde(default = "ReverbBank::default_primes")]
-    pub primes: f64,
-    #[serde(default = "ReverbBank::default_length")]
-    pub length: f64,
-    #[serde(default = "ReverbBank::default_phase")]
-    pub phase: u16,
+    // Anything left out of an old bank falls back to its default
+    let bank: ReverbBank = serde_json::from_str(r#"{"floor": 1000.0, "ceiling": 100.0}"#).unwrap();
+    assert_eq!(bank.gain, ReverbBank::default().gain);
+    assert_eq!(bank.damping_mode, None);
+    assert_eq!(bank.upgrade().damping_mode, Some(DampingMode::Legacy as u8));
+    assert_eq!(bank.upgrade().tone_mode, Some(ToneMode::Crossover as u8));
+    assert_eq!(ReverbBank::default().upgrade().tone_mode, Some(ToneMode::BandSplit as u8));
 }
 
-impl Default for ReverbBank
+// The bank itself is generated along with the parameters, see `reverb_parameters!`.
+impl ReverbBank
 {
-    fn default() -> Self
+    /// Fills in whatever older banks leave to be worked out from the rest.
+    pub fn upgrade(mut self) -> Self
     {
-        Self {
-            gain: Self::default_gain(),
-            wet: Self::default_wet(),
-            dry: Self::default_dry(),
-            feedback: Self::default_feedback(),
-            stereo_separation: Self::default_stereo_separation(),
-            ceiling: Self::default_ceiling(),
-            floor: Self::default_floor(),
-            prescence: Self::default_prescence(),
-            mids: Self::default_mids(),
-            mud: Self::default_mud(),
-            primes: Self::default_primes(),
-            length: Self::default_length(),
-            phase: Self::default_phase()
+        if self.damping_mode.is_none()
+        {
+            self.damping_mode = Some(DampingMode::legacy(self.floor, self.ceiling) as u8);
         }
-    }
-}
-
-impl From<&ReverbParameters> for ReverbBank
-{
-    fn from(param: &ReverbParameters) -> Self
-    {
-        let ReverbParameters {
-            gain,
-            wet,
-            dry,
-            feedback,
-            stereo_separation,
-            ceiling,
-            floor,
-            prescence,
-            mids,
-            mud,
-            primes,
-            length,
-            phase
-        } = param;
-        Self {
-            gain: gain.get() as f64,
-            wet: wet.get() as f64,
-            dry: dry.get() as f64,
-            feedback: feedback.get() as f64,
-            stereo_separation: stereo_separation.get() as f64,
-            ceiling: ceiling.get() as f64,
-            floor: floor.get() as f64,
-            prescence: prescence.get() as f64,
-            mids: mids.get() as f64,
-            mud: mud.get() as f64,
-            primes: primes.get() as f64,
-            length: length.get() as f64,
-            phase: phase.load(Ordering::Relaxed)
+        if self.tone_mode.is_none()
+        {
+            // Mids used to weight the middle band of the crossover
+            self.tone_mode = Some(ToneMode::Crossover as u8);
         }
-    }
-}
-
-impl ReverbBank
-{
-    fn default_gain() -> f64
-    {
-        LOG_MID
-    }
-    fn default_wet() -> f64
-    {
-        LOG_MID
-    }
-    fn default_dry() -> f64
-    {
-        LOG_MID
-    }
-    fn default_feedback() -> f64
-    {
-        0.5f64.powf(REVERB_CURVE as f64)
-    }
-    fn default_stereo_separation() -> f64
-    {
-        LOG_MID
-    }
-    fn default_floor() -> f64
-    {
-        FREQUENCY_MIN as f64
-    }
-    fn default_ceiling() -> f64
-    {
-        FREQUENCY_MAX as f64
-    }
-    fn default_prescence() -> f64
-    {
-        EQ_MID
-    }
-    fn default_mids() -> f64
-    {
-        EQ_MID
-    }
-    fn default_mud() -> f64
-    {
-        EQ_MID
-    }
-    fn default_primes() -> f64
-    {
-        1.0
-    }
-    fn default_length() -> f64
-    {
-        LOG_MID
-    }
-    fn default_phase() -> u16
-    {
-        0
+        self
     }
 }
\ No newline at end of file
@@ -1,68 +1,401 @@
-use core::{f64::consts::TAU, ops::Range};
+use core::{f64::consts::TAU, sync::atomic::Ordering};
 
-use real_time_fir_iir_filters::{conf::{All, HighPass}, filters::iir::first::FirstOrderFilter, param::{Omega, RC}, rtf::Rtf};
-use saturation::{tubes::Tube12AU7, Triode, TriodeClassA};
+use real_time_fir_iir_filters::{conf::{All, Peak}, filters::iir::{first::FirstOrderFilter, second::SecondOrderFilter}, param::{Omega, OmegaZeta, RC}, rtf::Rtf};
+use saturation::TriodeClassA;
 
-use crate::{reverb::M, FDNReverb, ReverbParameters, LOG_MID};
+use crate::{reverb::M, util::DenormalNoise, Character, FDNReverb, InputFilter, Oversampler, Oversampling, ReverbParameters, TmbToneStack, ToneMode, Transformer, TubeSettings, TubeStage, EQ_MAX, LOG_CURVE};
 
 pub const HEADROOM: f64 = 3.0;
 const G_PRE: f64 = 2.0;
 const G_POST: f64 = 1.0;
-const F_TRANSFORMER: f64 = 20.0;
 
-const TREBLE_F: f64 = 3000.0;
-const BASS_F: f64 = 440.0;
+const R_T: f64 = (458.0 - 440.0)/(8.6/2.2e3);
+const STAGE1: TriodeClassA<f64> = TriodeClassA {
+    r_i: 0.0,
+    r_p: R_T,
+    v_pp: 458.0,
+    v_c: 8.6
+};
+const STAGE1_RC: RC<f64> = RC {
+    r: 2.2e3*2.0,
+    c: 25e-6
+};
+/// The input stage sees the tone stack's boost and the input drive, so it gets twice the headroom.
+const STAGE1_HEADROOM: f64 = 2.0;
+const STAGE2: TriodeClassA<f64> = TriodeClassA {
+    r_i: R_T + 47e3,
+    r_p: 100e3,
+    v_pp: 410.0,
+    v_c: 2.0
+};
+const STAGE2_RC: RC<f64> = RC {
+    r: 820.0,
+    c: 25e-6
+};
+
+pub const TREBLE_F: f64 = 3000.0;
+pub const BASS_F: f64 = 440.0;
+pub const MID_F: f64 = 1000.0;
+pub const MID_Q: f64 = core::f64::consts::FRAC_1_SQRT_2;
+
+#[cfg(test)]
+#[test]
+fn tube_edges()
+{
+    use crate::{TubeBias, TubeCache, TubeType};
+
+    // Ramps slowly from well below the cache to well above it, and looks for steps in the output that stand out from their neighbours
+    fn is_continuous(edge: f64, mut saturate: impl FnMut(f64) -> f64) -> bool
+    {
+        const STEPS: usize = 200000;
+
+        let x = |n: usize| edge*3.0*(2.0*n as f64/STEPS as f64 - 1.0);
+        // Let the filters settle at the start first
+        for _ in 0..STEPS/10
+        {
+            saturate(x(0));
+        }
+        let y: Vec<f64> = (0..STEPS).map(|n| saturate(x(n)))
+            .collect();
+        let span = y.iter().copied().fold(f64::NEG_INFINITY, f64::max) - y.iter().copied().fold(f64::INFINITY, f64::min);
+        let steps: Vec<f64> = y.array_windows()
+            .map(|[y0, y1]| (y1 - y0).abs())
+            .collect();
+        y.iter().all(|y| y.is_finite()) && steps.array_windows().all(|&[s0, s1, s2]| s1 <= 4.0*s0.max(s2) + span*1e-6)
+    }
+
+    // Slow enough that the filters in the tubes barely matter
+    let rate = 44100.0*64.0;
+    for tube_type in TubeType::VARIANTS
+    {
+        let cache = TubeCache::DEFAULT.scaled(0.5);
+        let (mut tube1, mut tube2) = Channel::tubes(TubeSettings {
+            tube_type,
+            tube_bias: TubeBias::Warm,
+            cache
+        });
+        assert!(is_continuous(cache.scaled(STAGE1_HEADROOM).edge(), |x| tube1.saturate(rate, x)), "{}", tube_type.name());
+        assert!(is_continuous(cache.edge(), |x| tube2.saturate(rate, x)), "{}", tube_type.name());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn clean_level()
+{
+    use crate::Character;
+
+    const N: usize = 1 << 14;
+
+    // Quiet enough that the tubes are close to linear
+    let rate = 44100.0;
+    let x = |n: usize| 1e-3*(TAU*440.0*n as f64/rate).sin();
+    let param = ReverbParameters::default();
+    let [tube, clean] = [Character::Tube, Character::Clean].map(|character| {
+        param.character.store(character as u8, Ordering::Relaxed);
+        let mut channel = Channel::default();
+        channel.update(&param);
+        (0..N).map(|n| {
+                let z = channel.process_input(rate, x(n), 1.0, 1.0, 1.0, 1.0);
+                channel.process3(rate, z, 1.0, 0.0)[0]
+            })
+            .skip(N/2)
+            .collect::<Vec<_>>()
+    });
+
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a*b).sum::<f64>();
+    let (rms_tube, rms_clean) = (dot(&tube, &tube).sqrt(), dot(&clean, &clean).sqrt());
+    assert!((20.0*(rms_tube/rms_clean).log10()).abs() < 0.5, "{rms_tube} {rms_clean}");
+    assert!(dot(&tube, &clean) > 0.9*rms_tube*rms_clean);
+}
+
+#[cfg(test)]
+#[test]
+fn peaking_mid()
+{
+    use crate::magnitude;
+
+    const N: usize = 1 << 16;
+
+    let rate = 44100.0;
+    let param = ReverbParameters::default();
+    for (frequency, q) in [(1000.0, 0.7), (300.0, 4.0), (5000.0, 0.2)]
+    {
+        param.mid_frequency.set(frequency as f32);
+        param.mid_q.set(q);
+        for mids in [0.25, 1.0, 4.0]
+        {
+            let mut channel = Channel::default();
+            channel.update(&param);
+
+            let h: Vec<f64> = (0..N).map(|n| channel.peaking_mid(rate, if n == 0 {1.0} else {0.0}, mids))
+                .collect();
+
+            // The knob is the gain at the center, and far away it's flat
+            let center = magnitude(rate, &h, frequency);
+            println!("{frequency} Hz, Q = {q}, mids = {mids}: {center:.4}");
+            assert!((center - mids).abs() < mids*0.01);
+            assert!((magnitude(rate, &h, 10.0) - 1.0).abs() < 0.05);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn dry_latency()
+{
+    let rate = 44100.0;
+    let param = ReverbParameters::default();
+    for (oversampled, latency) in [(false, 0), (true, Channel::LATENCY)]
+    {
+        let mut channel = Channel::new(0, oversampled);
+        channel.update(&param);
+        assert_eq!(channel.latency(), latency);
+
+        // The dry signal lines up with the tubes, which only delay when they can oversample
+        let dry: Vec<f64> = (0..Channel::LATENCY*2).map(|n| {
+                channel.process_input(rate, if n == 0 {1.0} else {0.0}, 1.0, 1.0, 1.0, 1.0);
+                channel.process3(rate, 0.0, 0.0, 1.0)[1]
+            })
+            .collect();
+        assert_eq!(dry.iter().position(|&y| y != 0.0), Some(latency));
+    }
+}
+
+/// The input and output tube stages of a channel.
+pub type Tubes = (TubeStage<()>, TubeStage);
 
 #[derive(Debug, Clone)]
 pub struct Channel
 {
-    filter_transformer: [FirstOrderFilter<HighPass, f64>; 2],
+    transformer: [Transformer; 2],
     tone_stack: [FirstOrderFilter<All, f64>; 2],
-    tube1: Triode<f64, Tube12AU7, ()>,
-    tube2: Triode<f64, Tube12AU7>,
+    tone_stack_mid: SecondOrderFilter<Peak, f64>,
+    tone_stack_tmb: Option<TmbToneStack>,
+    tone_mode: ToneMode,
+    tube1: TubeStage<()>,
+    tube2: TubeStage,
+    character: Character,
+    input_drive: f64,
+    output_drive: f64,
+    oversampler: [Oversampler; 2],
+    oversampling: Oversampling,
+    oversampled: bool,
+    x_delay: [f64; Self::LATENCY],
+    x_index: usize,
     reverb: FDNReverb,
+    b: [f64; M],
+    noise: DenormalNoise,
     x: f64,
     z: f64,
 }
 
 impl Channel
 {
+    /// Latency of the tube stages when oversampled, which the dry signal is delayed by to stay aligned.
+    pub const LATENCY: usize = 2*Oversampler::LATENCY;
+
+    /// Latency of the channel, only there if it was made to oversample.
+    pub fn latency(&self) -> usize
+    {
+        if self.oversampled {Self::LATENCY} else {0}
+    }
+
     pub fn process1(&mut self, rate: f64, x: f64, gain: f64, mud: f64, mids: f64, prescence: f64) -> &[f64; M]
     {
-        self.x = x;
-        let [z_rest, z_treble] = self.tone_stack[0].filter(rate, self.x);
-        let [z_bass, z_mids] = self.tone_stack[1].filter(rate, z_rest);
-        self.z = z_bass*mud + z_mids*mids + z_treble*prescence;
+        self.process_input(rate, x, gain, mud, mids, prescence);
+        self.reverb.process1(rate)
+    }
+
+    /// Runs the input stage only, and returns what would be sent into the reverb. Used when the reverb is shared between channels.
+    pub fn process_input(&mut self, rate: f64, x: f64, gain: f64, mud: f64, mids: f64, prescence: f64) -> f64
+    {
+        self.x = self.delay_dry(x);
+        let x = x + self.noise.next();
+        if let Some(tmb) = &mut self.tone_stack_tmb
+        {
+            // The knobs turn the pots of the circuit, centered at unity
+            let pot = |v: f64| (v/EQ_MAX as f64).powf(1.0/LOG_CURVE).min(1.0);
+            self.z = tmb.filter(rate, x, [pot(prescence), pot(mids), pot(mud)]);
+        }
+        else
+        {
+            let [z_rest, z_treble] = self.tone_stack[0].filter(rate, x);
+            let [z_bass, z_mids] = self.tone_stack[1].filter(rate, z_rest);
+            if self.tone_mode == ToneMode::Crossover
+            {
+                self.z = z_bass*mud + z_mids*mids + z_treble*prescence;
+            }
+            else
+            {
+                self.z = self.peaking_mid(rate, z_bass*mud + z_mids + z_treble*prescence, mids);
+            }
+        }
 
         self.z *= G_PRE*gain;
-        self.z = self.tube1.saturate(rate, self.z)/HEADROOM;
-        [self.z] = self.filter_transformer[0].filter(rate, self.z);
+        // Clean mode still goes through the oversampler, so the latency stays the same
+        let tubes = self.character.tubes();
+        let oversampling = if tubes {self.oversampling} else {Oversampling::Off};
+        let rate_oversampled = rate*oversampling.factor() as f64;
+        let (tube, drive) = (&mut self.tube1, self.input_drive);
+        let mut saturate = |z| if tubes
+        {
+            // Drive pushes the tube harder, without changing the level much
+            tube.saturate(rate_oversampled, z*drive)/drive
+        }
+        else
+        {
+            // Keeps the level and polarity of the tube when it's quiet
+            z*tube.gain()
+        };
+        self.z = if self.oversampled {self.oversampler[0].process(oversampling, self.z, saturate)} else {saturate(self.z)};
+        self.z /= HEADROOM;
+        if self.character.transformer()
+        {
+            self.z = self.transformer[0].process(rate, self.z);
+        }
 
-        self.reverb.process1(rate)
+        self.z
+    }
+
+    /// Boosts or cuts around the mid frequency by `mids`, and leaves the rest flat.
+    fn peaking_mid(&mut self, rate: f64, x: f64, mids: f64) -> f64
+    {
+        // The peak filter tops out at Q, so it's scaled down to unity at the center
+        let [z_peak] = self.tone_stack_mid.filter(rate, x);
+        x + z_peak*2.0*self.tone_stack_mid.param.zeta*(mids - 1.0)
     }
 
-    pub fn process2(&mut self, rate: f64, z_avg: &[f64; M], wet: f64, dry: f64, stereo_separation: f64) -> f64
+    /// Returns the output of each line of the reverb, to be tapped into the output channels.
+    pub fn process2(&mut self, z_avg: &[f64; M], stereo_separation: f64) -> &[f64; M]
     {
-        self.z = self.reverb.process2(self.z, z_avg, stereo_separation);
+        let x = self.b.map(|b| b*self.z);
+        self.reverb.process2(&x, z_avg, stereo_separation)
+    }
 
-        [self.z] = self.filter_transformer[1].filter(rate, self.z);
+    /// Takes the reverb tapped into this channel's output. Returns the wet and dry signals separately, so the wet signal can be processed further
+    /// before they're summed.
+    pub fn process3(&mut self, rate: f64, y: f64, wet: f64, dry: f64) -> [f64; 2]
+    {
+        self.z = y;
+        if self.character.transformer()
+        {
+            self.z = self.transformer[1].process(rate, self.z);
+        }
         self.z *= G_POST;
-        self.z = self.tube2.saturate(rate, self.z)*HEADROOM;
+        let tubes = self.character.tubes();
+        let oversampling = if tubes {self.oversampling} else {Oversampling::Off};
+        let rate_oversampled = rate*oversampling.factor() as f64;
+        let (tube, drive) = (&mut self.tube2, self.output_drive);
+        let mut saturate = |z| if tubes
+        {
+            tube.saturate(rate_oversampled, z*drive)/drive
+        }
+        else
+        {
+            // Keeps the level and polarity of the tube when it's quiet
+            z*tube.gain()
+        };
+        self.z = if self.oversampled {self.oversampler[1].process(oversampling, self.z, saturate)} else {saturate(self.z)};
+        self.z *= HEADROOM;
+
+        [self.z*wet, self.x*dry]
+    }
+
+    /// For channels that bypass the reverb, like LFE. Only delays the dry signal by the same latency as the other channels.
+    pub fn process_bypass(&mut self, x: f64, dry: f64) -> [f64; 2]
+    {
+        self.x = self.delay_dry(x);
+
+        [0.0, self.x*dry]
+    }
 
-        self.z*wet + self.x/LOG_MID*dry
+    fn delay_dry(&mut self, x: f64) -> f64
+    {
+        if !self.oversampled
+        {
+            return x
+        }
+        let y = core::mem::replace(&mut self.x_delay[self.x_index], x);
+        self.x_index = (self.x_index + 1) % Self::LATENCY;
+        y
     }
 
     pub fn update(&mut self, params: &ReverbParameters)
     {
+        for transformer in self.transformer.iter_mut()
+        {
+            transformer.update(params);
+        }
+        // The treble crossover can't go below the bass one, which would leave the middle band with negative width
+        let bass_frequency = params.bass_frequency.get() as f64;
+        self.tone_stack[0].param.omega = bass_frequency.max(params.treble_frequency.get() as f64)*TAU;
+        self.tone_stack[1].param.omega = bass_frequency*TAU;
+        self.tone_stack_mid.param.assign(OmegaZeta {
+            omega: params.mid_frequency.get() as f64*TAU,
+            zeta: 0.5/params.mid_q.get() as f64
+        });
+        self.tone_mode = ToneMode::from_index(params.tone_mode.load(Ordering::Relaxed));
+        let components = self.tone_mode.components();
+        if self.tone_stack_tmb.as_ref().map(TmbToneStack::components) != components
+        {
+            self.tone_stack_tmb = components.map(TmbToneStack::new);
+        }
+
+        self.character = Character::from_index(params.character.load(Ordering::Relaxed));
+        self.input_drive = params.input_drive.get() as f64;
+        self.output_drive = params.output_drive.get() as f64;
+        if self.oversampled
+        {
+            self.oversampling = Oversampling::from_index(params.oversampling.load(Ordering::Relaxed));
+        }
+    }
+
+    /// Updates the channel's own reverb. Left alone while the reverb is shared between channels.
+    pub fn update_reverb(&mut self, params: &ReverbParameters, decay_scale: f64)
+    {
+        self.reverb.set_decay_scale(decay_scale);
         self.reverb.update(params);
     }
 
+    /// Swaps in tube stages from a [TubeBuilder](crate::TubeBuilder), and leaves the old ones in their place.
+    pub fn swap_tubes(&mut self, tubes: &mut Tubes)
+    {
+        core::mem::swap(&mut self.tube1, &mut tubes.0);
+        core::mem::swap(&mut self.tube2, &mut tubes.1);
+    }
+
+    /// Sets the weight of the input into each of the delay lines.
+    pub fn set_input_vector(&mut self, b: [f64; M])
+    {
+        self.b = b;
+    }
+
+    pub fn tail_size(&self, rate: f64) -> f64
+    {
+        self.reverb.tail_size(rate)
+    }
+
     pub fn suspend(&mut self)
     {
-        for filter in self.filter_transformer.iter_mut()
+        for transformer in self.transformer.iter_mut()
+        {
+            transformer.reset();
+        }
+        for filter in self.tone_stack.iter_mut()
         {
             filter.reset();
         }
+        self.tone_stack_mid.reset();
+        if let Some(tmb) = &mut self.tone_stack_tmb
+        {
+            tmb.reset();
+        }
+        for oversampler in self.oversampler.iter_mut()
+        {
+            oversampler.reset();
+        }
+        self.x_delay = [0.0; _];
         self.reverb.suspend();
     }
 }
@@ -71,16 +404,19 @@ impl Default for Channel
 {
     fn default() -> Self
     {
-        const RANGE: Range<f64> = -20.0..20.0;
-        const RESOLUTION: usize = 1024;
-        const R_T: f64 = (458.0 - 440.0)/(8.6/2.2e3);
+        Self::new(0, false)
+    }
+}
+
+impl Channel
+{
+    /// Channels sharing the same input need a different `decorrelation` index each, so their tails don't come out identical. Only `oversampled`
+    /// channels can oversample the tubes, at the cost of [Channel::LATENCY].
+    pub fn new(decorrelation: usize, oversampled: bool) -> Self
+    {
+        let (tube1, tube2) = Self::tubes(TubeSettings::DEFAULT);
         Self {
-            filter_transformer: [
-                FirstOrderFilter::new(Omega {
-                    omega: TAU*F_TRANSFORMER
-                });
-                2
-            ],
+            transformer: Default::default(),
             tone_stack: [
                 FirstOrderFilter::new(Omega {
                     omega: TAU*TREBLE_F
@@ -89,35 +425,37 @@ impl Default for Channel
                     omega: TAU*BASS_F
                 })
             ],
-            tube1: Triode::new(
-                TriodeClassA {
-                    r_i: 0.0,
-                    r_p: R_T,
-                    v_pp: 458.0,
-                    v_c: 8.6
-                }.cache(RANGE, RESOLUTION),
-                Default::default(),
-                RC {
-                    r: 2.2e3*2.0,
-                    c: 25e-6
-                }
-            ),
-            tube2: Triode::new(
-                TriodeClassA {
-                    r_i: R_T + 47e3,
-                    r_p: 100e3,
-                    v_pp: 410.0,
-                    v_c: 2.0
-                }.cache(RANGE, RESOLUTION),
-                Default::default(),
-                RC {
-                    r: 820.0,
-                    c: 25e-6
-                }
-            ),
-            reverb: FDNReverb::new(),
+            tone_stack_mid: SecondOrderFilter::new(OmegaZeta {
+                omega: TAU*MID_F,
+                zeta: 0.5/MID_Q
+            }),
+            tone_stack_tmb: None,
+            tone_mode: ToneMode::BandSplit,
+            tube1,
+            tube2,
+            character: Character::TubeTransformer,
+            input_drive: 1.0,
+            output_drive: 1.0,
+            oversampler: Default::default(),
+            oversampling: Oversampling::Off,
+            oversampled,
+            x_delay: [0.0; _],
+            x_index: 0,
+            reverb: FDNReverb::new(decorrelation),
+            b: [1.0; M],
+            noise: DenormalNoise::new(0xC0FFEE),
             x: 0.0,
             z: 0.0
         }
-    }   
-}
\ No newline at end of file
+    }
+
+    /// Only the output stage filters its input, as in the original circuit. Takes a while, see [TubeBuilder](crate::TubeBuilder).
+    pub fn tubes(settings: TubeSettings) -> Tubes
+    {
+        let TubeSettings {tube_type, tube_bias, cache} = settings;
+        (
+            TubeStage::<()>::new(tube_type, tube_bias.operating_point(STAGE1), STAGE1_RC, cache.scaled(STAGE1_HEADROOM)),
+            TubeStage::<InputFilter>::new(tube_type, tube_bias.operating_point(STAGE2), STAGE2_RC, cache)
+        )
+    }
+}
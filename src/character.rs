@@ -0,0 +1,49 @@
+/// Which of the analog stages color the signal going into and out of the reverb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Character
+{
+    /// Transparent digital reverb. The tubes are swapped for their small-signal gain, so quiet levels and polarity match the other modes.
+    Clean,
+    /// Tube stages, without the transformers.
+    Tube,
+    /// Tube stages coupled through transformers.
+    TubeTransformer
+}
+
+impl Character
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Clean,
+        Self::Tube,
+        Self::TubeTransformer
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::TubeTransformer)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Clean => "Clean",
+            Self::Tube => "Tube",
+            Self::TubeTransformer => "Tube + Transformer"
+        }
+    }
+
+    pub fn tubes(self) -> bool
+    {
+        !matches!(self, Self::Clean)
+    }
+
+    pub fn transformer(self) -> bool
+    {
+        matches!(self, Self::TubeTransformer)
+    }
+}
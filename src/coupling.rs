@@ -0,0 +1,128 @@
+use crate::Layout;
+
+#[cfg(test)]
+#[test]
+fn doubly_stochastic()
+{
+    for layout in [Layout::Stereo, Layout::Mono, Layout::MonoToStereo, Layout::Quad, Layout::Surround51, Layout::Surround71]
+    {
+        let n = layout.outputs();
+        for coupling in Coupling::VARIANTS
+        {
+            let mut k = vec![0.0; n*n];
+            coupling.matrix(layout, false, &mut k);
+            for i in 0..n
+            {
+                let row: f64 = (0..n).map(|j| k[i*n + j]).sum();
+                assert!((row - 1.0).abs() < 1e-12, "{layout:?} {coupling:?}");
+                for j in 0..n
+                {
+                    assert_eq!(k[i*n + j], k[j*n + i], "{layout:?} {coupling:?}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn mid_side_apart()
+{
+    for coupling in Coupling::VARIANTS
+    {
+        let mut k = [0.0; 4];
+        coupling.matrix(Layout::Stereo, true, &mut k);
+        assert_eq!(k, [1.0, 0.0, 0.0, 1.0], "{coupling:?}");
+    }
+}
+
+/// How the per-channel reverbs feed into eachother.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coupling
+{
+    /// Every channel feeds every other channel equally.
+    Merged,
+    /// No cross-feed at all.
+    Independent,
+    /// Each channel feeds its neighbours around the speaker circle.
+    Ring,
+    /// Like [Ring](Coupling::Ring), but without the wrap-around across the back of the room.
+    Adjacent
+}
+
+impl Coupling
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Merged,
+        Self::Independent,
+        Self::Ring,
+        Self::Adjacent
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::Merged)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Merged => "Merged",
+            Self::Independent => "Independent",
+            Self::Ring => "Ring",
+            Self::Adjacent => "Adjacent"
+        }
+    }
+
+    fn is_coupled(self, layout: Layout, mid_side: bool, i: usize, j: usize) -> bool
+    {
+        if i == j || mid_side || layout.is_lfe(i) || layout.is_lfe(j)
+        {
+            return false
+        }
+        let order = layout.speaker_order();
+        let (Some(a), Some(b)) = (order.iter().position(|&c| c == i), order.iter().position(|&c| c == j))
+        else
+        {
+            return false
+        };
+        let n = order.len();
+        match self
+        {
+            Self::Merged => true,
+            Self::Independent => false,
+            Self::Ring => a.abs_diff(b) == 1 || (n > 2 && a.abs_diff(b) == n - 1),
+            Self::Adjacent => a.abs_diff(b) == 1
+        }
+    }
+
+    /// Fills the `n`×`n` row-major coupling matrix for the given layout.
+    ///
+    /// Uses Metropolis weights, so the matrix is symmetric and doubly stochastic. It can never add energy to the loop, whatever the topology.
+    ///
+    /// In `mid_side` the channels are the mid and side of a stereo pair. Those are never coupled, as that would mix them back into left and right, and
+    /// let the mid and side decays bleed into eachother.
+    pub fn matrix(self, layout: Layout, mid_side: bool, k: &mut [f64])
+    {
+        let n = layout.outputs();
+        let degree = |i: usize| (0..n).filter(|&j| self.is_coupled(layout, mid_side, i, j)).count();
+
+        k.fill(0.0);
+        for i in 0..n
+        {
+            for j in 0..n
+            {
+                if self.is_coupled(layout, mid_side, i, j)
+                {
+                    k[i*n + j] = 1.0/(1 + degree(i).max(degree(j))) as f64;
+                }
+            }
+            k[i*n + i] = 1.0 - k[i*n..(i + 1)*n].iter().sum::<f64>();
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use core::f64::consts::TAU;
+
+use real_time_fir_iir_filters::{conf::{All, LowPass}, filters::iir::first::FirstOrderFilter, param::Omega, rtf::Rtf};
+
+const ORDER_MAX: usize = 4;
+
+/// Magnitude of the DFT of `x` at `frequency`.
+#[cfg(test)]
+pub fn magnitude(rate: f64, x: &[f64], frequency: f64) -> f64
+{
+    let w = TAU*frequency/rate;
+    let (re, im) = x.iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(re, im), (n, x)| (re + x*(w*n as f64).cos(), im - x*(w*n as f64).sin()));
+    re.hypot(im)
+}
+
+#[cfg(test)]
+#[test]
+fn slopes()
+{
+    const N: usize = 1 << 16;
+
+    let rate = 44100.0;
+
+    for slope in DampingSlope::VARIANTS
+    {
+        let expected = 6.0*slope.order() as f64;
+
+        // An octave apart, well beyond each corner
+        for (floor, ceiling, [f0, f1]) in [(20.0, 100.0, [2000.0, 1000.0]), (1000.0, 20000.0, [25.0, 50.0])]
+        {
+            let mut damping = Damping::new();
+            damping.set_slope(slope);
+            damping.set_floor(TAU*floor);
+            damping.set_ceiling(TAU*ceiling);
+
+            let h: Vec<f64> = (0..N).map(|n| damping.process(rate, if n == 0 {1.0} else {0.0}))
+                .collect();
+            let db = 20.0*(magnitude(rate, &h, f1)/magnitude(rate, &h, f0)).log10();
+
+            println!("{}: {floor}..{ceiling} Hz, {db:.2} dB/oct", slope.name());
+            assert!((db - expected).abs() < 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn modes()
+{
+    const N: usize = 1 << 16;
+
+    let rate = 44100.0;
+
+    for mode in DampingMode::VARIANTS
+    {
+        for slope in DampingSlope::VARIANTS
+        {
+            for (floor, ceiling) in [(100.0, 1000.0), (1000.0, 100.0)]
+            {
+                let mut damping = Damping::new();
+                damping.set_mode(mode);
+                damping.set_slope(slope);
+                damping.set_floor(TAU*floor);
+                damping.set_ceiling(TAU*ceiling);
+
+                let h: Vec<f64> = (0..N).map(|n| damping.process(rate, if n == 0 {1.0} else {0.0}))
+                    .collect();
+
+                // Matches the analog prototype well below Nyquist, and never goes above the peak gain
+                for frequency in [20.0, 50.0, 100.0, 200.0, 316.0, 500.0, 1000.0, 2000.0, 4000.0]
+                {
+                    let expected = damping.response(TAU*frequency);
+                    let measured = magnitude(rate, &h, frequency);
+                    println!("{} {} {floor}..{ceiling} Hz: {frequency} Hz, {expected:.4} ~ {measured:.4}", mode.name(), slope.name());
+                    assert!((measured - expected).abs() < 0.01 + expected*0.05);
+                    assert!(measured <= damping.peak_gain() + 0.01);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn legacy()
+{
+    const N: usize = 1 << 12;
+
+    let rate = 44100.0;
+    let (floor, ceiling) = (TAU*2000.0, TAU*200.0);
+
+    for slope in DampingSlope::VARIANTS
+    {
+        let mut damping = Damping::new();
+        damping.set_mode(DampingMode::Legacy);
+        damping.set_slope(slope);
+        damping.set_floor(floor);
+        damping.set_ceiling(ceiling);
+
+        // The cascade banks used to get with floor above ceiling, from its own filters
+        let order = slope.order();
+        let mut low: Vec<FirstOrderFilter<LowPass, f64>> = [floor, ceiling].into_iter()
+            .flat_map(|omega| core::iter::repeat_n(FirstOrderFilter::new(Omega {omega}), order))
+            .collect();
+        let mut high: Vec<FirstOrderFilter<All, f64>> = vec![FirstOrderFilter::new(Omega {omega: floor}); order];
+
+        for n in 0..N
+        {
+            let x = if n == 0 {1.0} else {0.0};
+            let expected = low.iter_mut().fold(x, |x, f| f.filter(rate, x)[0]) + high.iter_mut().fold(x, |x, f| f.filter(rate, x)[1]);
+            assert!((damping.process(rate, x) - expected).abs() < 1e-12, "{}", slope.name());
+        }
+    }
+}
+
+/// Steepness of the floor and ceiling filters in the loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DampingSlope
+{
+    Db6,
+    Db12,
+    Db24
+}
+
+impl DampingSlope
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Db6,
+        Self::Db12,
+        Self::Db24
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::Db6)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Db6 => "6 dB/oct",
+            Self::Db12 => "12 dB/oct",
+            Self::Db24 => "24 dB/oct"
+        }
+    }
+
+    /// Number of first-order sections on each side.
+    pub fn order(self) -> usize
+    {
+        match self
+        {
+            Self::Db6 => 1,
+            Self::Db12 => 2,
+            Self::Db24 => 4
+        }
+    }
+}
+
+/// Shape of the damping in the loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DampingMode
+{
+    /// Keeps what's between floor and ceiling.
+    BandPass,
+    /// Cuts what's between floor and ceiling, whichever is higher.
+    BandReject,
+    /// Shelves gently from floor to ceiling. Darkens when floor is below ceiling, and thins out when it's above.
+    Tilt,
+    /// What banks from before the mode existed did. Band-pass while floor is below ceiling, and above it, cuts between them with the low side also
+    /// cut at floor.
+    Legacy
+}
+
+impl DampingMode
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::BandPass,
+        Self::BandReject,
+        Self::Tilt,
+        Self::Legacy
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::BandPass)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::BandPass => "Band-Pass",
+            Self::BandReject => "Band-Reject",
+            Self::Tilt => "Tilt",
+            Self::Legacy => "Legacy"
+        }
+    }
+
+    /// Banks from before the mode existed switched to their own band-reject implicitly whenever floor was above ceiling.
+    pub fn legacy(floor: f64, ceiling: f64) -> Self
+    {
+        if floor > ceiling
+        {
+            Self::Legacy
+        }
+        else
+        {
+            Self::BandPass
+        }
+    }
+}
+
+/// Floor and ceiling filters of one delay line, as cascades of first-order sections.
+#[derive(Debug, Clone)]
+pub struct Damping
+{
+    floor: [FirstOrderFilter<All, f64>; ORDER_MAX],
+    /// The extra low-pass at floor in [DampingMode::Legacy].
+    floor_low: [FirstOrderFilter<LowPass, f64>; ORDER_MAX],
+    ceiling: [FirstOrderFilter<All, f64>; ORDER_MAX],
+    slope: DampingSlope,
+    mode: DampingMode
+}
+
+impl Damping
+{
+    pub fn new() -> Self
+    {
+        Self {
+            floor: [
+                FirstOrderFilter::new(Omega {
+                    omega: TAU*220.0
+                });
+                _
+            ],
+            floor_low: [
+                FirstOrderFilter::new(Omega {
+                    omega: TAU*220.0
+                });
+                _
+            ],
+            ceiling: [
+                FirstOrderFilter::new(Omega {
+                    omega: TAU*220.0
+                });
+                _
+            ],
+            slope: DampingSlope::Db6,
+            mode: DampingMode::BandPass
+        }
+    }
+
+    pub fn floor(&self) -> f64
+    {
+        self.floor[0].param.omega
+    }
+
+    pub fn ceiling(&self) -> f64
+    {
+        self.ceiling[0].param.omega
+    }
+
+    pub fn slope(&self) -> DampingSlope
+    {
+        self.slope
+    }
+
+    pub fn set_floor(&mut self, omega: f64)
+    {
+        for filter in self.floor.iter_mut()
+        {
+            filter.param.omega = omega;
+        }
+        for filter in self.floor_low.iter_mut()
+        {
+            filter.param.omega = omega;
+        }
+    }
+
+    pub fn set_ceiling(&mut self, omega: f64)
+    {
+        for filter in self.ceiling.iter_mut()
+        {
+            filter.param.omega = omega;
+        }
+    }
+
+    /// Sections that are switched in start from rest.
+    pub fn set_slope(&mut self, slope: DampingSlope)
+    {
+        if self.slope != slope
+        {
+            self.slope = slope;
+            self.reset();
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: DampingMode)
+    {
+        if self.mode != mode
+        {
+            self.mode = mode;
+            self.reset();
+        }
+    }
+
+    /// Gain of the tilt's shelf at high frequencies, relative to DC, per section.
+    fn tilt(&self) -> f64
+    {
+        self.floor()/self.ceiling()
+    }
+
+    /// Magnitude response at `omega`, of the analog prototype.
+    #[cfg(test)]
+    pub fn response(&self, omega: f64) -> f64
+    {
+        use num::complex::Complex64;
+
+        let s = Complex64::new(0.0, omega);
+        let low = |omega_0: f64| 1.0/(1.0 + s/omega_0);
+        let high = |omega_0: f64| (s/omega_0)/(1.0 + s/omega_0);
+
+        let order = self.slope.order() as i32;
+        let [omega_f, omega_c] = [self.floor(), self.ceiling()];
+        match self.mode
+        {
+            DampingMode::BandPass => (high(omega_f)*low(omega_c)).powi(order),
+            DampingMode::BandReject => low(omega_f.min(omega_c)).powi(order) + high(omega_f.max(omega_c)).powi(order),
+            DampingMode::Tilt => (low(omega_f) + high(omega_f)*self.tilt()).powi(order)/self.tilt().max(1.0).powi(order),
+            DampingMode::Legacy if omega_f > omega_c => (low(omega_f)*low(omega_c)).powi(order) + high(omega_f).powi(order),
+            DampingMode::Legacy => (high(omega_f)*low(omega_c)).powi(order)
+        }.norm()
+    }
+
+    /// Peak gain over all frequencies.
+    pub fn peak_gain(&self) -> f64
+    {
+        match self.mode
+        {
+            // Unity at DC, where the low side of the cut doesn't reach
+            DampingMode::Legacy if self.floor() > self.ceiling() => 1.0,
+            // Peaks at the geometric mean of floor and ceiling
+            DampingMode::BandPass | DampingMode::Legacy => {
+                let [omega_f, omega_c] = [self.floor(), self.ceiling()];
+                (omega_c/(omega_f + omega_c)).powi(self.slope.order() as i32)
+            },
+            // Unity at both ends, and normalized to unity for tilt
+            DampingMode::BandReject | DampingMode::Tilt => 1.0
+        }
+    }
+
+    pub fn process(&mut self, rate: f64, x: f64) -> f64
+    {
+        let order = self.slope.order();
+        let floor_above = self.floor() > self.ceiling();
+
+        match self.mode
+        {
+            DampingMode::Legacy if floor_above => {
+                let low = self.floor_low[..order].iter_mut()
+                    .fold(x, |x, f| f.filter(rate, x)[0]);
+                let low = self.ceiling[..order].iter_mut()
+                    .fold(low, |x, f| f.filter(rate, x)[0]);
+                let high = self.floor[..order].iter_mut()
+                    .fold(x, |x, f| f.filter(rate, x)[1]);
+                low + high
+            },
+            DampingMode::BandPass | DampingMode::Legacy => {
+                let high = self.floor[..order].iter_mut()
+                    .fold(x, |x, f| f.filter(rate, x)[1]);
+                self.ceiling[..order].iter_mut()
+                    .fold(high, |x, f| f.filter(rate, x)[0])
+            },
+            DampingMode::BandReject => {
+                let (low, high) = if floor_above
+                {
+                    (&mut self.ceiling, &mut self.floor)
+                }
+                else
+                {
+                    (&mut self.floor, &mut self.ceiling)
+                };
+                let low = low[..order].iter_mut()
+                    .fold(x, |x, f| f.filter(rate, x)[0]);
+                let high = high[..order].iter_mut()
+                    .fold(x, |x, f| f.filter(rate, x)[1]);
+                low + high
+            },
+            DampingMode::Tilt => {
+                let tilt = self.tilt();
+                let norm = 1.0/tilt.max(1.0);
+                self.floor[..order].iter_mut()
+                    .fold(x, |x, f| {
+                        let [low, high] = f.filter(rate, x);
+                        (low + high*tilt)*norm
+                    })
+            }
+        }
+    }
+
+    pub fn reset(&mut self)
+    {
+        for filter in self.floor.iter_mut()
+            .chain(self.ceiling.iter_mut())
+        {
+            filter.reset();
+        }
+        for filter in self.floor_low.iter_mut()
+        {
+            filter.reset();
+        }
+    }
+}
+
+impl Default for Damping
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
@@ -0,0 +1,576 @@
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+use vst::util::AtomicFloat;
+
+use crate::{ReverbParameters, LEVEL_MIN_DB};
+
+/// How the travel of the knob maps onto the stored value of a parameter.
+#[derive(Debug, Clone, Copy)]
+pub enum Scale
+{
+    Linear {min: f32, max: f32},
+    /// Stored as `max*value^curve`.
+    Curve {curve: f32, max: f32},
+    /// Evenly spaced in octaves.
+    Log {min: f32, max: f32},
+    /// A linear gain, with the knob linear in dB from [LEVEL_MIN_DB] up to `max_db`. The very bottom mutes.
+    Level {max_db: f32},
+    /// One of `count` discrete steps.
+    Steps {count: usize},
+    Switch
+}
+
+impl Scale
+{
+    /// Knob position of a stored value.
+    pub fn normalize(self, x: f32) -> f32
+    {
+        match self
+        {
+            Self::Linear {min, max} => (x - min)/(max - min),
+            Self::Curve {curve, max} => (x/max).powf(1.0/curve),
+            Self::Log {min, max} => (x.log2() - min.log2())/(max.log2() - min.log2()),
+            Self::Level {max_db} => if x > 0.0
+            {
+                ((20.0*x.log10() - LEVEL_MIN_DB)/(max_db - LEVEL_MIN_DB)).clamp(0.0, 1.0)
+            }
+            else
+            {
+                0.0
+            },
+            Self::Steps {count} => x/(count - 1) as f32,
+            Self::Switch => x
+        }
+    }
+
+    /// Stored value at a knob position.
+    pub fn denormalize(self, value: f32) -> f32
+    {
+        match self
+        {
+            Self::Linear {min, max} => min + value*(max - min),
+            Self::Curve {curve, max} => value.powf(curve)*max,
+            Self::Log {min, max} => (value*(max.log2() - min.log2()) + min.log2()).exp2(),
+            Self::Level {max_db} => if value > 0.0
+            {
+                10.0f32.powf((value*(max_db - LEVEL_MIN_DB) + LEVEL_MIN_DB)/20.0)
+            }
+            else
+            {
+                0.0
+            },
+            Self::Steps {count} => (value*(count - 1) as f32).round(),
+            Self::Switch => if value >= 0.5 {1.0} else {0.0}
+        }
+    }
+}
+
+/// What the host shows, and what can be typed back in.
+#[derive(Debug, Clone, Copy)]
+pub enum Unit
+{
+    None,
+    /// Stored as a fraction. Curved parameters show how far along the knob they are instead.
+    Percent,
+    Hertz,
+    /// Stored in dB.
+    Decibel,
+    /// Stored as a linear gain, and shown in dB.
+    Gain,
+    /// Stored as a linear gain against a reference level instead of unity, and shown in dBr. Typed dB are read the same.
+    RelativeGain,
+    /// Stored in seconds, and shown in milliseconds.
+    Milliseconds,
+    Volts,
+    /// Named steps.
+    Names(fn(u8) -> &'static str),
+    OnOff
+}
+
+impl Unit
+{
+    pub fn symbol(self) -> &'static str
+    {
+        match self
+        {
+            Self::None | Self::Names(_) | Self::OnOff => "",
+            Self::Percent => "%",
+            Self::Hertz => "Hz",
+            Self::Decibel | Self::Gain => "dB",
+            Self::RelativeGain => "dBr",
+            Self::Milliseconds => "ms",
+            Self::Volts => "V"
+        }
+    }
+}
+
+/// End of the knob where the parameter switches off, and shows as such.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Off
+{
+    Never,
+    Bottom,
+    Top
+}
+
+/// A physical quantity worked out from several parameters, shown in place of the parameter's own value.
+#[derive(Debug, Clone, Copy)]
+pub struct Derived
+{
+    pub unit: &'static str,
+    /// The quantity with the knob at a position, and everything else as it is. Has to rise with the knob, so typed values can be found on it.
+    pub quantity: fn(&ReverbParameters, f32) -> f64,
+    pub text: fn(&ReverbParameters, f32) -> String
+}
+
+impl Derived
+{
+    /// Knob position that comes closest to the typed quantity. Anything after a `/` is left out, so only the first of several quantities is
+    /// read. [None] if the quantity doesn't change along the knob, or jumps over the typed one instead of reaching it.
+    pub fn parse(&self, param: &ReverbParameters, text: &str) -> Option<f32>
+    {
+        let target = parse_quantity(text.split('/').next()?, self.unit)? as f64;
+        if target.is_nan()
+        {
+            return None
+        }
+        let quantity = |value| (self.quantity)(param, value);
+
+        let (mut lo, mut hi) = (0.0, 1.0);
+        let (bottom, top) = (quantity(lo), quantity(hi));
+        if bottom.is_nan() || top.is_nan() || bottom == top
+        {
+            return None
+        }
+        if bottom >= target
+        {
+            return Some(lo)
+        }
+        if top <= target
+        {
+            return Some(hi)
+        }
+        for _ in 0..32
+        {
+            let mid = 0.5*(lo + hi);
+            if quantity(mid) < target
+            {
+                lo = mid
+            }
+            else
+            {
+                hi = mid
+            }
+        }
+        let closest = if target - quantity(lo) < quantity(hi) - target {lo} else {hi};
+        ((quantity(closest) - target).abs() <= Self::TOLERANCE*target.abs()).then_some(closest)
+    }
+
+    /// How far off the quantity at the found knob position can be, relative to the typed one.
+    const TOLERANCE: f64 = 1e-3;
+}
+
+/// Everything the host needs to know about a parameter.
+#[derive(Debug, Clone, Copy)]
+pub struct ParamDescriptor
+{
+    pub name: &'static str,
+    pub unit: Unit,
+    pub scale: Scale,
+    pub off: Off,
+    pub derived: Option<Derived>,
+    pub automatable: bool,
+    /// Glides towards its target every sample, see [ParameterSmoother](crate::ParameterSmoother). Otherwise it's taken once per block.
+    pub smoothed: bool,
+    /// Whether the parameter has any effect with the rest as they are. Shows as off if not.
+    pub applies: fn(&ReverbParameters) -> bool
+}
+
+impl ParamDescriptor
+{
+    pub const DEFAULT: Self = Self {
+        name: "",
+        unit: Unit::None,
+        scale: Scale::Linear {min: 0.0, max: 1.0},
+        off: Off::Never,
+        derived: None,
+        automatable: true,
+        smoothed: false,
+        applies: |_| true
+    };
+
+    fn is_off(&self, x: f32) -> bool
+    {
+        let value = self.scale.normalize(x);
+        match self.off
+        {
+            Off::Never => false,
+            Off::Bottom => value <= 0.0,
+            Off::Top => value >= 1.0
+        }
+    }
+
+    pub fn label(&self, x: f32) -> &'static str
+    {
+        if self.is_off(x)
+        {
+            return ""
+        }
+        self.unit.symbol()
+    }
+
+    pub fn text(&self, x: f32) -> String
+    {
+        if self.is_off(x)
+        {
+            return "Off".to_string()
+        }
+        match (self.unit, self.scale)
+        {
+            (Unit::Names(name), _) => name(x as u8).to_string(),
+            (Unit::OnOff, _) => if x >= 0.5 {"On"} else {"Off"}.to_string(),
+            (Unit::Gain | Unit::RelativeGain, _) if x <= 0.0 => "-inf".to_string(),
+            (_, Scale::Steps {..}) => format!("{x}"),
+            (unit, scale) => format!("{:.3}", match (unit, scale)
+            {
+                (Unit::Percent, Scale::Curve {..}) => 100.0*scale.normalize(x),
+                (Unit::Percent, _) => 100.0*x,
+                (Unit::Gain | Unit::RelativeGain, _) => 20.0*x.log10(),
+                (Unit::Milliseconds, _) => 1000.0*x,
+                _ => x
+            })
+        }
+    }
+
+    /// Knob position of typed text, or [None] if it makes no sense for this parameter. Quantities can be given in any unit that can be converted
+    /// to the displayed one.
+    pub fn parse(&self, text: &str) -> Option<f32>
+    {
+        if self.off != Off::Never && text.trim().eq_ignore_ascii_case("off")
+        {
+            return Some(if self.off == Off::Top {1.0} else {0.0})
+        }
+        let value = match (self.unit, self.scale)
+        {
+            (Unit::Names(name), Scale::Steps {count}) => {
+                let text = text.trim();
+                let index = (0..count).position(|i| name(i as u8).eq_ignore_ascii_case(text))
+                    .or_else(|| text.parse().ok())?;
+                self.scale.normalize(index as f32)
+            },
+            (Unit::OnOff, _) => match text.trim().to_ascii_lowercase().as_str()
+            {
+                "on" | "true" => 1.0,
+                "off" | "false" => 0.0,
+                text => text.parse().ok()?
+            },
+            (unit, scale) => {
+                let shown = parse_quantity(text, unit.symbol())
+                    .or_else(|| if matches!(unit, Unit::RelativeGain) {parse_quantity(text, Unit::Gain.symbol())} else {None})?;
+                match (unit, scale)
+                {
+                    (Unit::Percent, Scale::Curve {..}) => shown/100.0,
+                    (Unit::Percent, _) => scale.normalize(shown/100.0),
+                    (Unit::Gain | Unit::RelativeGain, _) => scale.normalize(10.0f32.powf(shown/20.0)),
+                    (Unit::Milliseconds, _) => scale.normalize(shown/1000.0),
+                    _ => scale.normalize(shown)
+                }
+            }
+        };
+        (!value.is_nan()).then(|| value.clamp(0.0, 1.0))
+    }
+}
+
+/// Reads typed text as a quantity in `unit`. A bare number is taken to already be in `unit`, and kilo and milli prefixes are understood.
+fn parse_quantity(text: &str, unit: &str) -> Option<f32>
+{
+    let text = text.trim();
+    if let Ok(x) = text.parse()
+    {
+        return Some(x)
+    }
+    let number = text.trim_end_matches(|c: char| c.is_alphabetic() || c == '%');
+    let suffix = &text[number.len()..];
+    let x: f32 = number.trim().parse().ok()?;
+    let scale = if suffix.eq_ignore_ascii_case(unit)
+    {
+        1.0
+    }
+    else if suffix.strip_prefix(['k', 'K']).is_some_and(|suffix| suffix.eq_ignore_ascii_case(unit))
+    {
+        1e3
+    }
+    else if suffix.strip_prefix('m').is_some_and(|suffix| suffix.eq_ignore_ascii_case(unit))
+    {
+        1e-3
+    }
+    else if unit.strip_prefix('m').is_some_and(|unit| suffix.eq_ignore_ascii_case(unit))
+    {
+        1e3
+    }
+    else
+    {
+        return None
+    };
+    Some(x*scale)
+}
+
+/// Atomic storage of a parameter, seen as an [f32].
+pub trait AtomicParam
+{
+    fn new_value(x: f32) -> Self
+    where
+        Self: Sized;
+    fn value(&self) -> f32;
+    fn set_value(&self, x: f32);
+}
+
+impl AtomicParam for AtomicFloat
+{
+    fn new_value(x: f32) -> Self
+    {
+        Self::new(x)
+    }
+    fn value(&self) -> f32
+    {
+        self.get()
+    }
+    fn set_value(&self, x: f32)
+    {
+        self.set(x)
+    }
+}
+
+impl AtomicParam for AtomicU8
+{
+    fn new_value(x: f32) -> Self
+    {
+        Self::new(x as u8)
+    }
+    fn value(&self) -> f32
+    {
+        self.load(Ordering::Relaxed) as f32
+    }
+    fn set_value(&self, x: f32)
+    {
+        self.store(x as u8, Ordering::Relaxed)
+    }
+}
+
+impl AtomicParam for AtomicU16
+{
+    fn new_value(x: f32) -> Self
+    {
+        Self::new(x as u16)
+    }
+    fn value(&self) -> f32
+    {
+        self.load(Ordering::Relaxed) as f32
+    }
+    fn set_value(&self, x: f32)
+    {
+        self.store(x as u16, Ordering::Relaxed)
+    }
+}
+
+impl AtomicParam for AtomicBool
+{
+    fn new_value(x: f32) -> Self
+    {
+        Self::new(x >= 0.5)
+    }
+    fn value(&self) -> f32
+    {
+        self.load(Ordering::Relaxed) as u8 as f32
+    }
+    fn set_value(&self, x: f32)
+    {
+        self.store(x >= 0.5, Ordering::Relaxed)
+    }
+}
+
+/// How a parameter is saved in a [ReverbBank](crate::ReverbBank).
+pub trait BankValue
+{
+    fn to_param(self) -> f32;
+    fn from_param(x: f32) -> Self;
+}
+
+impl BankValue for f64
+{
+    fn to_param(self) -> f32
+    {
+        self as f32
+    }
+    fn from_param(x: f32) -> Self
+    {
+        x as f64
+    }
+}
+
+impl BankValue for u8
+{
+    fn to_param(self) -> f32
+    {
+        self as f32
+    }
+    fn from_param(x: f32) -> Self
+    {
+        x as u8
+    }
+}
+
+impl BankValue for u16
+{
+    fn to_param(self) -> f32
+    {
+        self as f32
+    }
+    fn from_param(x: f32) -> Self
+    {
+        x as u16
+    }
+}
+
+impl BankValue for bool
+{
+    fn to_param(self) -> f32
+    {
+        self as u8 as f32
+    }
+    fn from_param(x: f32) -> Self
+    {
+        x >= 0.5
+    }
+}
+
+/// Left out of older banks. Filled in by [ReverbBank::upgrade](crate::ReverbBank::upgrade) before it gets here.
+impl BankValue for Option<u8>
+{
+    fn to_param(self) -> f32
+    {
+        self.unwrap_or_default() as f32
+    }
+    fn from_param(x: f32) -> Self
+    {
+        Some(x as u8)
+    }
+}
+
+/// Generates [ReverbParam](crate::ReverbParam), [ReverbParameters](crate::ReverbParameters) and [ReverbBank](crate::ReverbBank) from a single
+/// table of parameters. Each row reads
+///
+/// `Variant => field: Atomic as Bank {descriptor} [per scale] = default;`
+///
+/// where the field doubles as the key in saved banks, the descriptor fills in a [ParamDescriptor], the bank stores the value divided by the
+/// optional `scale`, and `default` is the value in the bank. Attributes on a row go onto the field of the bank. State that isn't a parameter,
+/// and isn't saved, goes in a `state` block at the start.
+macro_rules! reverb_parameters {
+    (
+        state {
+            $(
+                $(#[$state_attr:meta])*
+                $state:ident: $state_ty:ty = $state_init:expr;
+            )*
+        }
+        $(
+            $(#[$attr:meta])*
+            $variant:ident => $field:ident: $atomic:ident as $bank:ty {$($desc:tt)*} $(per $scale:ident)? = $default:expr;
+        )*
+    ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum ReverbParam
+        {
+            $($variant),*
+        }
+
+        impl ReverbParam
+        {
+            pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+            pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+                $(Self::$variant),*
+            ];
+
+            pub fn descriptor(self) -> $crate::descriptor::ParamDescriptor
+            {
+                match self
+                {
+                    $(
+                        Self::$variant => $crate::descriptor::ParamDescriptor {
+                            $($desc)*,
+                            ..$crate::descriptor::ParamDescriptor::DEFAULT
+                        }
+                    ),*
+                }
+            }
+        }
+
+        pub struct ReverbParameters
+        {
+            $(
+                $(#[$state_attr])*
+                pub $state: $state_ty,
+            )*
+            $(pub $field: $atomic),*
+        }
+
+        impl ReverbParameters
+        {
+            fn atomic(&self, param: ReverbParam) -> &dyn $crate::descriptor::AtomicParam
+            {
+                match param
+                {
+                    $(ReverbParam::$variant => &self.$field),*
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+        #[serde(default)]
+        pub struct ReverbBank
+        {
+            $(
+                $(#[$attr])*
+                pub $field: $bank
+            ),*
+        }
+
+        impl Default for ReverbBank
+        {
+            fn default() -> Self
+            {
+                Self {
+                    $($field: $default),*
+                }
+            }
+        }
+
+        impl From<ReverbBank> for ReverbParameters
+        {
+            fn from(bank: ReverbBank) -> Self
+            {
+                use $crate::descriptor::{AtomicParam, BankValue};
+
+                let bank = bank.upgrade();
+                Self {
+                    $($state: $state_init,)*
+                    $($field: $atomic::new_value(BankValue::to_param(bank.$field $(/$scale)?))),*
+                }
+            }
+        }
+
+        impl From<&ReverbParameters> for ReverbBank
+        {
+            fn from(param: &ReverbParameters) -> Self
+            {
+                use $crate::descriptor::{AtomicParam, BankValue};
+
+                Self {
+                    $($field: <$bank as BankValue>::from_param(param.$field.value()) $(*$scale)?),*
+                }
+            }
+        }
+    };
+}
+pub(crate) use reverb_parameters;
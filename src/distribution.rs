@@ -0,0 +1,60 @@
+use crate::{reverb::M, util};
+
+/// How the input of each channel is distributed over the delay lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputDistribution
+{
+    /// Every channel feeds every line equally.
+    Uniform,
+    /// Each channel feeds its own subset of the lines.
+    Interleaved,
+    /// Each channel feeds every line, but with a sign pattern orthogonal to the other channels.
+    Orthogonal
+}
+
+impl InputDistribution
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Uniform,
+        Self::Interleaved,
+        Self::Orthogonal
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::Uniform)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Uniform => "Uniform",
+            Self::Interleaved => "Interleaved",
+            Self::Orthogonal => "Orthogonal"
+        }
+    }
+
+    /// Input vector of channel `c` out of `n`. Every vector carries the same energy as [Uniform](InputDistribution::Uniform).
+    pub fn vector(self, c: usize, n: usize) -> [f64; M]
+    {
+        match self
+        {
+            Self::Uniform => [1.0; M],
+            Self::Interleaved => {
+                let a = (n as f64).sqrt();
+                core::array::from_fn(|i| if i % n == c % n {a} else {0.0})
+            },
+            Self::Orthogonal => {
+                // The first row of the Hadamard matrix is all ones, so skip it
+                let h = util::hadamard_matrix::<M>();
+                let a = (M as f64).sqrt();
+                h[(c + 1) % M].map(|h| h*a)
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+/// Which reverb engine does the work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine
+{
+    /// Every channel has its own FDN.
+    PerChannel,
+    /// All channels feed one shared FDN through their input vectors, and take decorrelated taps from it. Saves CPU.
+    ///
+    /// There's only one loop, so coupling, stereo separation and the mid/side decays are off.
+    Shared
+}
+
+impl Engine
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::PerChannel,
+        Self::Shared
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::PerChannel)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::PerChannel => "Per Channel",
+            Self::Shared => "Shared"
+        }
+    }
+}
@@ -0,0 +1,187 @@
+#[cfg(test)]
+#[test]
+fn channels()
+{
+    for layout in Layout::VARIANTS
+    {
+        let lfe = (0..layout.outputs()).filter(|&c| layout.is_lfe(c)).count();
+        let expected = match layout
+        {
+            Layout::Mono => (1, 1, 0),
+            Layout::Stereo => (2, 2, 0),
+            Layout::MonoToStereo => (1, 2, 0),
+            Layout::Quad => (4, 4, 0),
+            Layout::Surround51 => (6, 6, 1),
+            Layout::Surround71 => (8, 8, 1)
+        };
+        assert_eq!((layout.inputs(), layout.outputs(), lfe), expected, "{layout:?}");
+
+        // Every speaker but the LFE, once each
+        let mut order = layout.speaker_order().to_vec();
+        assert!(order.iter().all(|&c| !layout.is_lfe(c)), "{layout:?}");
+        order.sort();
+        order.dedup();
+        assert_eq!(order.len() + lfe, layout.outputs(), "{layout:?}");
+        assert!(order.iter().all(|&c| c < layout.outputs()), "{layout:?}");
+
+        assert_eq!(Layout::from_name(layout.name()), Some(layout));
+    }
+    assert_eq!(Layout::Quad.speaker_order(), &[2, 0, 1, 3]);
+    assert_eq!(Layout::Surround51.speaker_order(), &[4, 0, 2, 1, 5]);
+    assert_eq!(Layout::Surround71.speaker_order(), &[4, 6, 0, 2, 1, 7, 5]);
+    assert_eq!(Layout::from_name(" 5.1 "), Some(Layout::Surround51));
+    assert_eq!(Layout::from_name("hexagonal"), None);
+}
+
+/// Channel layout of the plugin.
+///
+/// VST2 has no way of negotiating layouts with the host, so the layout is decided when the plugin is instantiated, from the `FDNVERB_LAYOUT`
+/// environment variable. Stereo is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout
+{
+    Stereo,
+    Mono,
+    MonoToStereo,
+    Quad,
+    Surround51,
+    Surround71
+}
+
+impl Layout
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Stereo,
+        Self::Mono,
+        Self::MonoToStereo,
+        Self::Quad,
+        Self::Surround51,
+        Self::Surround71
+    ];
+
+    /// Environment variable holding the [name](Layout::name) of the layout new instances get.
+    pub const ENV: &str = "FDNVERB_LAYOUT";
+
+    /// The layout to give a new instance. Anything unset or unknown falls back to stereo.
+    pub fn instance() -> Self
+    {
+        std::env::var(Self::ENV).ok()
+            .and_then(|name| Self::from_name(&name))
+            .unwrap_or(Self::Stereo)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Stereo => "Stereo",
+            Self::Mono => "Mono",
+            Self::MonoToStereo => "Mono to Stereo",
+            Self::Quad => "Quad",
+            Self::Surround51 => "5.1",
+            Self::Surround71 => "7.1"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        let name = name.trim();
+        Self::VARIANTS.into_iter()
+            .find(|layout| layout.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Index of the LFE channel in VST surround layouts (L R C LFE Ls Rs ...).
+    const LFE: usize = 3;
+
+    pub const fn inputs(self) -> usize
+    {
+        match self
+        {
+            Self::Mono | Self::MonoToStereo => 1,
+            _ => self.outputs()
+        }
+    }
+
+    pub const fn outputs(self) -> usize
+    {
+        match self
+        {
+            Self::Mono => 1,
+            Self::Stereo | Self::MonoToStereo => 2,
+            Self::Quad => 4,
+            Self::Surround51 => 6,
+            Self::Surround71 => 8
+        }
+    }
+
+    /// Which input feeds the given output channel.
+    pub const fn input(self, output: usize) -> usize
+    {
+        if self.inputs() == 1
+        {
+            0
+        }
+        else
+        {
+            output
+        }
+    }
+
+    /// Outputs that share an input need decorrelated reverbs, otherwise they'd just be copies of eachother.
+    pub const fn is_decorrelated(self) -> bool
+    {
+        self.inputs() < self.outputs()
+    }
+
+    /// The LFE channel is passed through dry, and doesn't take part in the reverb.
+    pub const fn is_lfe(self, channel: usize) -> bool
+    {
+        matches!(self, Self::Surround51 | Self::Surround71) && channel == Self::LFE
+    }
+
+    /// Channels in the order they sit around the listener, starting at the back left. The LFE channel is left out.
+    pub const fn speaker_order(self) -> &'static [usize]
+    {
+        match self
+        {
+            Self::Mono => &[0],
+            Self::Stereo | Self::MonoToStereo => &[0, 1],
+            Self::Quad => &[2, 0, 1, 3],
+            Self::Surround51 => &[4, 0, 2, 1, 5],
+            Self::Surround71 => &[4, 6, 0, 2, 1, 7, 5]
+        }
+    }
+
+    pub const fn unique_id_offset(self) -> i32
+    {
+        self as i32
+    }
+
+    pub fn channel_name(self, channel: usize) -> (&'static str, &'static str)
+    {
+        const SURROUND: [(&str, &str); 8] = [
+            ("Left", "L"),
+            ("Right", "R"),
+            ("Centre", "C"),
+            ("LFE", "LFE"),
+            ("Left Surround", "Ls"),
+            ("Right Surround", "Rs"),
+            ("Side Left", "Sl"),
+            ("Side Right", "Sr")
+        ];
+        const QUAD: [(&str, &str); 4] = [
+            ("Left", "L"),
+            ("Right", "R"),
+            ("Left Surround", "Ls"),
+            ("Right Surround", "Rs")
+        ];
+        match self
+        {
+            Self::Mono => ("Mono", "M"),
+            Self::Quad => QUAD.get(channel).copied().unwrap_or(("", "")),
+            _ => SURROUND.get(channel).copied().unwrap_or(("", ""))
+        }
+    }
+}
@@ -1,5 +1,4 @@
 #![feature(variant_count)]
-#![feature(array_chunks)]
 #![feature(generic_arg_infer)]
 #![feature(more_float_constants)]
 #![feature(array_windows)]
@@ -10,28 +9,40 @@
 #![feature(allocator_api)]
 #![feature(impl_trait_in_bindings)]
 #![feature(future_join)]
-#![feature(array_try_map)]
 #![feature(generic_const_exprs)]
 
-use std::sync::Arc;
+use std::{f64::consts::FRAC_1_SQRT_2, sync::{atomic::Ordering, Arc}};
 
 use num::Float;
-use vst::{prelude::*, plugin_main};
+use vst::{channels::ChannelInfo, prelude::*, plugin_main};
 
 moddef::moddef!(
     flat mod {
         bank,
         channel,
+        character,
+        coupling,
+        damping,
+        descriptor,
+        distribution,
+        engine,
+        layout,
+        oversampling,
+        panning,
         parameters,
-        reverb
+        reverb,
+        silence,
+        smoothing,
+        tone_stack,
+        transformer,
+        tube,
+        width
     },
     mod {
         util
     }
 );
 
-const CHANNEL_COUNT: usize = 2;
-
 const EQ_MAX: f32 = 10.0;
 const LOG_MID: f64 = 0.1;
 
@@ -54,12 +65,109 @@ fn it_works()
 
     let mut c = Channel::default();
     c.update(&param);
+    c.update_reverb(&param, 1.0);
     
     let z_avg = *c.process1(rate, x, 0.5, 0.5, 0.5, 0.5);
-    c.process2(rate, &z_avg, 0.5, 0.5, 0.5);
+    let y = c.process2(&z_avg, 0.5).iter().sum();
+    c.process3(rate, y, 0.5, 0.5);
 
     let z_avg = *c.process1(rate, x, 0.5, 0.5, 0.5, 0.5);
-    c.process2(rate, &z_avg, 0.5, 0.5, 0.5);
+    let y = c.process2(&z_avg, 0.5).iter().sum();
+    c.process3(rate, y, 0.5, 0.5);
+}
+
+#[cfg(test)]
+#[test]
+fn endless_tail()
+{
+    let mut plugin = ReverbPlugin::new(Default::default());
+    plugin.param.feedback.set(1.0);
+    for mode in [DampingMode::BandReject, DampingMode::Tilt]
+    {
+        plugin.param.damping_mode.store(mode as u8, Ordering::Relaxed);
+        plugin.resume();
+        assert_eq!(plugin.get_tail_size(), isize::MAX, "{}", mode.name());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn sleep_and_wake()
+{
+    use vst::host::HostBuffer;
+
+    const BLOCK: usize = 512;
+
+    let mut plugin = ReverbPlugin::new(Default::default());
+    plugin.param.feedback.set(0.3);
+    plugin.param.length.set(0.01);
+    plugin.set_sample_rate(44100.0);
+    plugin.resume();
+
+    let inputs = plugin.layout.inputs();
+    let outputs = plugin.layout.outputs();
+    let mut host_buffer = HostBuffer::new(inputs, outputs);
+    let mut block = |plugin: &mut ReverbPlugin, impulse: bool| {
+        let mut x = vec![vec![0.0f32; BLOCK]; inputs];
+        if impulse
+        {
+            x.iter_mut().for_each(|x| x[0] = 1.0);
+        }
+        let mut y = vec![vec![0.0f32; BLOCK]; outputs];
+        plugin.process(&mut host_buffer.bind(&x, &mut y));
+        y.iter().flatten().any(|&y| y != 0.0)
+    };
+
+    // Rings out after an impulse, and falls asleep some time after the tail has died
+    assert!(block(&mut plugin, true));
+    let tail = plugin.get_tail_size() as usize;
+    let mut blocks = 1;
+    while !plugin.silence.sleeping()
+    {
+        assert!(blocks*BLOCK < 4*tail + 2*BLOCK, "still awake after {} samples, tail is {tail}", blocks*BLOCK);
+        block(&mut plugin, false);
+        blocks += 1;
+    }
+    assert!(blocks*BLOCK > tail);
+
+    // Skips processing while nothing comes in, and wakes on the very block that something does
+    for _ in 0..8
+    {
+        assert!(!block(&mut plugin, false));
+        assert!(plugin.silence.sleeping());
+    }
+    assert!(block(&mut plugin, true));
+    assert!(!plugin.silence.sleeping());
+}
+
+#[cfg(test)]
+#[test]
+fn shared_stereo()
+{
+    const N: usize = 1 << 14;
+
+    let mut plugin = ReverbPlugin::new(Default::default());
+    plugin.param.engine.store(Engine::Shared as u8, Ordering::Relaxed);
+    plugin.engine = Engine::Shared;
+    plugin.resume();
+
+    // The left output, for an impulse into each input on its own
+    let [l, r] = [0, 1].map(|c| {
+        let mut shared = plugin.shared.clone();
+        (0..N).map(|n| {
+                shared.process1(plugin.rate);
+                let x = plugin.shared_taps[c].map(|b| if n == 0 {b} else {0.0});
+                shared.process2(&x, &[0.0; M], 1.0).iter()
+                    .zip(plugin.shared_taps[0].iter())
+                    .map(|(y, t)| y*t)
+                    .sum::<f64>()
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a*b).sum::<f64>();
+    assert!(dot(&l, &l) > 0.0 && dot(&r, &r) > 0.0);
+    assert!(dot(&l, &r).abs() < 0.5*(dot(&l, &l)*dot(&r, &r)).sqrt());
 }
 
 //const TREBLE_CUT_CURVE: f64 = 0.15200309344504995;
@@ -68,68 +176,330 @@ const LOG_CURVE: f64 = 3.321928094887362;
 struct ReverbPlugin
 {
     pub param: Arc<ReverbParameters>,
-    channels: [Channel; CHANNEL_COUNT],
+    layout: Layout,
+    channels: Vec<Channel>,
+    // Along with whether the matrix keeps mid and side apart
+    coupling: (Coupling, bool),
+    coupling_matrix: Vec<f64>,
+    z: Vec<[f64; M]>,
+    distribution: InputDistribution,
+    spread: f64,
+    taps: Vec<[f64; M]>,
+    tapped: Vec<f64>,
+    engine: Engine,
+    shared: FDNReverb,
+    // Each channel goes in and out of the shared reverb through the same vector
+    shared_taps: Vec<[f64; M]>,
+    y: Vec<[f64; 2]>,
+    width: StereoWidth,
+    silence: SilenceDetector,
+    smoother: ParameterSmoother,
+    tube_builder: TubeBuilder<Tubes>,
     rate: f64
 }
 
 impl ReverbPlugin
 {
-    fn process<'a, F: Float>(&mut self, buffer: &mut AudioBuffer<'a, F>)
+    /// Fixed when the instance is made, see [Oversampling::ENV].
+    fn latency(&self) -> usize
+    {
+        self.channels.iter()
+            .map(Channel::latency)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Mid/side only makes sense with a true stereo input.
+    fn mid_side(&self) -> bool
     {
-        let gain = self.param.gain.get() as f64;
-        let wet = self.param.wet.get() as f64;
-        let dry = self.param.dry.get() as f64;
-        let stereo_separation = self.param.stereo_separation.get() as f64;
-        let stereo_merging = 1.0 - stereo_separation;
-        let prescence = self.param.prescence.get() as f64;
-        let mids = self.param.mids.get() as f64;
-        let mud = self.param.mud.get() as f64;
+        self.layout == Layout::Stereo && self.param.mid_side.load(Ordering::Relaxed)
+    }
 
-        for channel in self.channels.iter_mut()
+    /// Updates only the reverbs the engine uses. The shared one can't decay mid and side apart.
+    fn update_reverbs(&mut self, mid_side: bool)
+    {
+        let param = self.smoother.params();
+        match self.engine
         {
-            channel.update(&self.param);
+            Engine::PerChannel => {
+                let decay_scales = if mid_side
+                {
+                    [param.mid_decay.get() as f64, param.side_decay.get() as f64]
+                }
+                else
+                {
+                    [1.0; 2]
+                };
+                for (c, channel) in self.channels.iter_mut()
+                    .enumerate()
+                {
+                    channel.update_reverb(param, decay_scales.get(c).copied().unwrap_or(1.0));
+                }
+            },
+            Engine::Shared => self.shared.update(param)
         }
+    }
 
+    fn process<'a, F: Float>(&mut self, buffer: &mut AudioBuffer<'a, F>)
+    {
+        let layout = self.layout;
+        let mid_side = self.mid_side();
+        let samples = buffer.samples();
         let (input_buffer, mut output_buffer) = buffer.split();
 
-        let mut input = input_buffer.into_iter().map(|i| i.iter()).array_chunks::<CHANNEL_COUNT>().next().unwrap();
-        let mut output = output_buffer.into_iter().map(|o| o.iter_mut()).array_chunks::<CHANNEL_COUNT>().next().unwrap();
+        // Skip processing entirely while the tail is dead and nothing is coming in
+        let input_peak = (0..input_buffer.len()).flat_map(|i| input_buffer.get(i))
+            .map(|x| x.abs().to_f64().unwrap())
+            .fold(0.0, f64::max);
+        self.smoother.block(&self.param);
+        if self.silence.input(input_peak)
+        {
+            // Nothing to glide over
+            self.smoother.settle();
+            for output in output_buffer.into_iter()
+            {
+                output.fill(F::zero());
+            }
+            return
+        }
 
-        let input = input.each_mut();
-        let output = output.each_mut();
+        let engine = Engine::from_index(self.param.engine.load(Ordering::Relaxed));
+        if engine != self.engine
+        {
+            // Don't let the tail of the previous engine come back when switching back to it
+            self.engine = engine;
+            self.suspend();
+        }
 
-        while let (Some(x), Some(y)) = (
-            input.try_map(|input| input.next()),
-            output.try_map(|output| output.next())
-        )
+        // New tube stages are built off the audio thread, and swapped in once they're done
+        self.tube_builder.request(TubeSettings::from_params(&self.param), self.channels.len());
+        if let Some(mut tubes) = self.tube_builder.take()
+        {
+            for (channel, tubes) in self.channels.iter_mut()
+                .zip(tubes.iter_mut())
+            {
+                channel.swap_tubes(tubes)
+            }
+            self.tube_builder.recycle(tubes)
+        }
+
+        let _denormal_guard = util::DenormalGuard::enable();
+
+        let mut len = 0;
+        let mut energy = 0.0;
+
+        let coupling = (Coupling::from_index(self.param.coupling.load(Ordering::Relaxed)), mid_side);
+        if coupling != self.coupling
+        {
+            self.coupling = coupling;
+            coupling.0.matrix(layout, mid_side, &mut self.coupling_matrix);
+        }
+        let distribution = InputDistribution::from_index(self.param.input_distribution.load(Ordering::Relaxed));
+        if distribution != self.distribution
         {
-            let mut z_avg = [0.0; M];
+            self.distribution = distribution;
+            for (c, channel) in self.channels.iter_mut()
+                .enumerate()
+            {
+                channel.set_input_vector(distribution.vector(c, layout.outputs()));
+            }
+        }
+        let n_channels = self.channels.len();
 
-            for (x, channel) in x.into_iter()
-                .zip(self.channels.iter_mut())
+        let [mut gain, mut wet, mut dry] = [0.0; 3];
+        let [mut input_trim, mut output_trim] = [1.0; 2];
+        let [mut stereo_separation, mut stereo_merging] = [0.0; 2];
+        let [mut width, mut bass_mono] = [0.0; 2];
+        let [mut prescence, mut mids, mut mud] = [0.0; 3];
+        let mut sends = [1.0; 2];
+
+        let mut pending = false;
+        for n in 0..samples
+        {
+            // Continuous parameters glide every sample, so only update the DSP while they're moving
+            let moved = self.smoother.next(self.rate);
+            if moved || n == 0
             {
-                let x = x.to_f64().unwrap();
-                let z = channel.process1(self.rate, x, gain, mud, mids, prescence);
-                for (z_avg, z) in z_avg.iter_mut()
-                    .zip(z)
+                let param = self.smoother.params();
+                // Equal-power crossfade on top of wet and dry, leaving them exactly as they are at the center
+                let [mix_dry, mix_wet] = util::crossfade(param.mix.get() as f64);
+                // Gain and wet at 0 dBr are where they've always been, while dry at 0 dB is unity
+                gain = param.gain.get() as f64*LOG_MID;
+                wet = param.wet.get() as f64*LOG_MID*mix_wet;
+                dry = param.dry.get() as f64*mix_dry;
+                input_trim = 10.0f64.powf(param.input_trim.get() as f64/20.0);
+                output_trim = 10.0f64.powf(param.output_trim.get() as f64/20.0);
+                stereo_separation = param.stereo_separation.get() as f64;
+                stereo_merging = 1.0 - stereo_separation;
+                width = param.width.get() as f64;
+                bass_mono = param.bass_mono.get() as f64;
+                if mid_side
                 {
-                    *z_avg += *z;
+                    sends = [param.mid_send.get() as f64, param.side_send.get() as f64];
                 }
             }
 
+            // Filters and delay lines are costlier to update, so they follow at a lower rate
+            pending |= moved;
+            if n == 0 || pending && n % UPDATE_INTERVAL == 0
             {
-                let a = stereo_merging/CHANNEL_COUNT as f64;
-                for z_avg in z_avg.iter_mut()
+                pending = false;
+                let param = self.smoother.params();
+                prescence = param.prescence.get() as f64;
+                mids = param.mids.get() as f64;
+                mud = param.mud.get() as f64;
+                let spread = param.spread.get() as f64;
+                if spread != self.spread
+                {
+                    self.spread = spread;
+                    output_taps(layout, spread, &mut self.taps);
+                }
+
+                for channel in self.channels.iter_mut()
                 {
-                    *z_avg *= a
+                    channel.update(param);
                 }
+                self.update_reverbs(mid_side);
             }
 
-            for (y, channel) in y.into_iter()
-                .zip(self.channels.iter_mut())
+            let input = |i: usize| {
+                if i < input_buffer.len()
+                {
+                    input_buffer.get(i)[n].to_f64().unwrap()*input_trim
+                }
+                else
+                {
+                    0.0
+                }
+            };
+            let x = |c: usize| {
+                if mid_side
+                {
+                    // Encode L/R to M/S
+                    let [l, r] = [input(0), input(1)];
+                    (if c == 0 {l + r} else {l - r})*FRAC_1_SQRT_2
+                }
+                else
+                {
+                    input(layout.input(c))
+                }
+            };
+
+            match engine
+            {
+                Engine::PerChannel => {
+                    // The LFE channel doesn't take part in the reverb
+                    for ((c, channel), z) in self.channels.iter_mut()
+                        .enumerate()
+                        .zip(self.z.iter_mut())
+                        .filter(|((c, _), _)| !layout.is_lfe(*c))
+                    {
+                        let send = sends.get(c).copied().unwrap_or(1.0);
+                        *z = *channel.process1(self.rate, x(c), gain*send, mud, mids, prescence);
+                    }
+
+                    // Feed the coupled signal back into each reverb, and tap its lines into the outputs
+                    self.tapped.fill(0.0);
+                    for (c, channel) in self.channels.iter_mut()
+                        .enumerate()
+                        .filter(|&(c, _)| !layout.is_lfe(c))
+                    {
+                        let mut z_coupled = [0.0; M];
+                        for (&k, z) in self.coupling_matrix[c*n_channels..(c + 1)*n_channels].iter()
+                            .zip(self.z.iter())
+                        {
+                            let a = stereo_merging*k;
+                            for (z_coupled, z) in z_coupled.iter_mut()
+                                .zip(z)
+                            {
+                                *z_coupled += a*z;
+                            }
+                        }
+                        let lines = channel.process2(&z_coupled, stereo_separation);
+                        for (tapped, taps) in self.tapped.iter_mut()
+                            .zip(self.taps[c*n_channels..(c + 1)*n_channels].iter())
+                        {
+                            *tapped += taps.iter()
+                                .zip(lines)
+                                .map(|(t, y)| t*y)
+                                .sum::<f64>();
+                        }
+                    }
+                },
+                Engine::Shared => {
+                    self.shared.process1(self.rate);
+
+                    // The LFE channel doesn't take part in the reverb
+                    let mut x_shared = [0.0; M];
+                    for (c, channel) in self.channels.iter_mut()
+                        .enumerate()
+                        .filter(|&(c, _)| !layout.is_lfe(c))
+                    {
+                        let send = sends.get(c).copied().unwrap_or(1.0);
+                        let z = channel.process_input(self.rate, x(c), gain*send, mud, mids, prescence);
+                        for (x_shared, &b) in x_shared.iter_mut()
+                            .zip(self.shared_taps[c].iter())
+                        {
+                            *x_shared += b*z;
+                        }
+                    }
+
+                    // Each channel is fed in and tapped out through its own orthogonal vector, so they stay apart in the one reverb
+                    let lines = self.shared.process2(&x_shared, &[0.0; M], 1.0);
+                    for (tapped, taps) in self.tapped.iter_mut()
+                        .zip(self.shared_taps.iter())
+                    {
+                        *tapped = taps.iter()
+                            .zip(lines)
+                            .map(|(t, y)| t*y)
+                            .sum::<f64>();
+                    }
+                }
+            }
+
+            for (c, (channel, y)) in self.channels.iter_mut()
+                .zip(self.y.iter_mut())
+                .enumerate()
+            {
+                *y = if layout.is_lfe(c)
+                {
+                    channel.process_bypass(x(c), dry)
+                }
+                else
+                {
+                    channel.process3(self.rate, self.tapped[c], wet, dry)
+                };
+            }
+            if mid_side
             {
-                *y = F::from(channel.process2(self.rate, &z_avg, wet, dry, stereo_separation)).unwrap()
+                // Decode M/S back to L/R
+                for k in 0..2
+                {
+                    let [m, s] = [self.y[0][k], self.y[1][k]];
+                    self.y[0][k] = (m + s)*FRAC_1_SQRT_2;
+                    self.y[1][k] = (m - s)*FRAC_1_SQRT_2;
+                }
+            }
+            if layout.outputs() == 2
+            {
+                [self.y[0][0], self.y[1][0]] = self.width.process(self.rate, [self.y[0][0], self.y[1][0]], width, bass_mono);
+            }
+            for (c, &[y_wet, y_dry]) in self.y.iter()
+                .enumerate()
+            {
+                let y = (y_wet + y_dry)*output_trim;
+                energy += y*y;
+                if c < output_buffer.len()
+                {
+                    output_buffer.get_mut(c)[n] = F::from(y).unwrap()
+                }
             }
+            len += 1;
+        }
+
+        if len > 0 && self.silence.output(len, energy/(len*self.channels.len()) as f64, self.get_tail_size().max(0) as usize)
+        {
+            self.suspend()
         }
     }
 }
@@ -141,28 +511,65 @@ impl Plugin for ReverbPlugin
     where
         Self: Sized
     {
+        let layout = Layout::instance();
+        let oversampled = Oversampling::available();
+        let param: Arc<ReverbParameters> = Default::default();
+        param.oversampled.store(oversampled, Ordering::Relaxed);
         ReverbPlugin {
             /*runtime: tokio::runtime::Builder::new_current_thread()
                 .global_queue_interval(128)
                 .worker_threads(2)
                 .build()
                 .expect("Invalid runtime."),*/
-            param: Default::default(),
-            channels: Default::default(),
+            param,
+            layout,
+            channels: (0..layout.outputs()).map(|c| Channel::new(if layout.is_decorrelated() {c} else {0}, oversampled))
+                .collect(),
+            coupling: (Coupling::Merged, false),
+            coupling_matrix: {
+                let mut k = vec![0.0; layout.outputs()*layout.outputs()];
+                Coupling::Merged.matrix(layout, false, &mut k);
+                k
+            },
+            z: vec![[0.0; M]; layout.outputs()],
+            distribution: InputDistribution::Uniform,
+            spread: 0.0,
+            taps: {
+                let mut taps = vec![[0.0; M]; layout.outputs()*layout.outputs()];
+                output_taps(layout, 0.0, &mut taps);
+                taps
+            },
+            tapped: vec![0.0; layout.outputs()],
+            engine: Engine::PerChannel,
+            shared: FDNReverb::new(0),
+            shared_taps: (0..layout.outputs()).map(|k| if layout.is_lfe(k)
+                {
+                    [0.0; M]
+                }
+                else
+                {
+                    InputDistribution::Orthogonal.vector(k, layout.outputs())
+                })
+                .collect(),
+            y: vec![[0.0; 2]; layout.outputs()],
+            width: StereoWidth::new(),
+            silence: Default::default(),
+            smoother: Default::default(),
+            tube_builder: TubeBuilder::new(TubeSettings::DEFAULT, Channel::tubes),
             rate: 44100.0
         }
     }
 
     fn get_tail_size(&self) -> isize
     {
-        // Magic numbers... teehee
-        const TAIL_A: f64 = 0.0000009932959;
-        const TAIL_B: f64 = 17.4476084084878;
-
-        let length = (D as f64/5000.0)*self.param.length.get() as f64;
-        let feedback = self.param.feedback.get() as f64;
-
-        (self.rate*length*TAIL_A*(feedback*TAIL_B).exp()) as isize
+        // Endless at full feedback, which saturates
+        (match self.engine
+        {
+            Engine::PerChannel => self.channels.iter()
+                .map(|channel| channel.tail_size(self.rate))
+                .fold(0.0, f64::max),
+            Engine::Shared => self.shared.tail_size(self.rate)
+        }.ceil() as isize).saturating_add(self.latency() as isize)
     }
 
     fn get_info(&self) -> Info
@@ -172,28 +579,51 @@ impl Plugin for ReverbPlugin
             vendor: "Soma FX".to_string(),
             presets: 0,
             parameters: ReverbParam::VARIANT_COUNT as i32,
-            inputs: CHANNEL_COUNT as i32,
-            outputs: CHANNEL_COUNT as i32,
+            inputs: self.layout.inputs() as i32,
+            outputs: self.layout.outputs() as i32,
             midi_inputs: 0,
             midi_outputs: 0,
-            unique_id: 1323532,
+            unique_id: 1323532 + self.layout.unique_id_offset(),
             version: 1,
             category: Category::Effect,
-            initial_delay: 0,
+            initial_delay: self.latency() as i32,
             preset_chunks: false,
             f64_precision: true,
             silent_when_stopped: true
         }
     }
 
+    fn get_input_info(&self, input: i32) -> ChannelInfo
+    {
+        let (name, short_name) = match self.layout
+        {
+            Layout::MonoToStereo => Layout::Mono.channel_name(input as usize),
+            layout => layout.channel_name(input as usize)
+        };
+        ChannelInfo::new(name.to_string(), Some(short_name.to_string()), true, None)
+    }
+
+    fn get_output_info(&self, output: i32) -> ChannelInfo
+    {
+        let (name, short_name) = self.layout.channel_name(output as usize);
+        ChannelInfo::new(name.to_string(), Some(short_name.to_string()), true, None)
+    }
+
     fn set_sample_rate(&mut self, rate: f32)
     {
         self.rate = rate as f64;
+        self.param.rate.set(rate);
     }
 
     fn resume(&mut self)
     {
-        
+        self.smoother.reset(&self.param);
+        for channel in self.channels.iter_mut()
+        {
+            channel.update(self.smoother.params());
+        }
+        self.update_reverbs(self.mid_side());
+        self.silence.reset()
     }
 
     fn suspend(&mut self)
@@ -202,6 +632,8 @@ impl Plugin for ReverbPlugin
         {
             channel.suspend()
         }
+        self.shared.suspend();
+        self.width.suspend()
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>)
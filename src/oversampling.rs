@@ -0,0 +1,267 @@
+use core::f64::consts::{PI, TAU};
+
+/// Taps of each half-band filter. Must be `4k + 3`, so every other tap besides the center is zero.
+const TAPS: usize = 47;
+const CENTER: usize = TAPS/2;
+const STAGES: usize = 3;
+const FACTOR_MAX: usize = 1 << STAGES;
+/// Delay through one stage, up and down, in samples at its higher rate.
+const STAGE_DELAY: usize = 2*CENTER;
+
+#[cfg(test)]
+#[test]
+fn latency()
+{
+    for oversampling in Oversampling::VARIANTS
+    {
+        let mut oversampler = Oversampler::new();
+
+        let y: Vec<f64> = (0..Oversampler::LATENCY*4).map(|n| oversampler.process(oversampling, if n == 0 {1.0} else {0.0}, |x| x))
+            .collect();
+        let peak = y.iter()
+            .enumerate()
+            .fold((0, 0.0), |(i, a), (j, &y)| if y.abs() > a {(j, y.abs())} else {(i, a)})
+            .0;
+        let dc: f64 = y.iter()
+            .sum();
+
+        println!("{} peak = {peak}, dc = {dc}", oversampling.name());
+        assert_eq!(peak, Oversampler::LATENCY);
+        assert!((dc - 1.0).abs() < 1e-6);
+    }
+}
+
+/// How much the nonlinear stages are oversampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling
+{
+    Off,
+    X2,
+    X4,
+    X8
+}
+
+impl Oversampling
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Off,
+        Self::X2,
+        Self::X4,
+        Self::X8
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::Off)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Off => "Off",
+            Self::X2 => "2x",
+            Self::X4 => "4x",
+            Self::X8 => "8x"
+        }
+    }
+
+    /// Environment variable that opts new instances into oversampling, when set to `On`.
+    ///
+    /// Oversampling delays the output by [Oversampler::LATENCY], and hosts only read the latency once, when the instance is made. So instances
+    /// that opt in report that latency from the start, whatever the setting. The rest have none, and only [Off](Oversampling::Off).
+    pub const ENV: &str = "FDNVERB_OVERSAMPLING";
+
+    /// Whether a new instance can oversample.
+    pub fn available() -> bool
+    {
+        std::env::var(Self::ENV).is_ok_and(|value| value.trim().eq_ignore_ascii_case("on"))
+    }
+
+    pub fn stages(self) -> usize
+    {
+        self as usize
+    }
+
+    pub fn factor(self) -> usize
+    {
+        1 << self.stages()
+    }
+}
+
+/// One 2x stage, upsampling and downsampling through the same linear-phase half-band filter.
+#[derive(Debug, Clone)]
+struct HalfBand
+{
+    up: [f64; TAPS/2 + 1],
+    down: [f64; TAPS]
+}
+
+impl HalfBand
+{
+    fn new() -> Self
+    {
+        Self {
+            up: [0.0; _],
+            down: [0.0; _]
+        }
+    }
+
+    /// Polyphase interpolation. The even phase takes the even taps, and the odd phase is just the delayed center tap.
+    fn upsample(&mut self, h: &[f64; TAPS], x: f64) -> [f64; 2]
+    {
+        self.up.copy_within(..TAPS/2, 1);
+        self.up[0] = x;
+
+        let even = self.up.iter()
+            .zip(h.iter().step_by(2))
+            .map(|(x, h)| x*h)
+            .sum::<f64>();
+        let odd = self.up[CENTER/2]*h[CENTER];
+
+        [even*2.0, odd*2.0]
+    }
+
+    /// Decimation. Only the kept phase is computed.
+    fn downsample(&mut self, h: &[f64; TAPS], [x0, x1]: [f64; 2]) -> f64
+    {
+        self.down.copy_within(..TAPS - 1, 1);
+        self.down[0] = x0;
+
+        let y = self.down.iter()
+            .zip(h)
+            .map(|(x, h)| x*h)
+            .sum();
+
+        self.down.copy_within(..TAPS - 1, 1);
+        self.down[0] = x1;
+
+        y
+    }
+
+    fn reset(&mut self)
+    {
+        self.up = [0.0; _];
+        self.down = [0.0; _];
+    }
+}
+
+/// Runs a nonlinear stage at a multiple of the host rate.
+///
+/// The latency is padded to [Oversampler::LATENCY] for every factor, so it can be reported to the host once.
+#[derive(Debug, Clone)]
+pub struct Oversampler
+{
+    h: [f64; TAPS],
+    stages: [HalfBand; STAGES],
+    pad: [f64; Self::LATENCY],
+    pad_index: usize,
+    oversampling: Oversampling
+}
+
+impl Oversampler
+{
+    /// Latency in samples at the host rate.
+    pub const LATENCY: usize = (STAGE_DELAY*(FACTOR_MAX - 1)).div_ceil(FACTOR_MAX);
+
+    pub fn new() -> Self
+    {
+        // Blackman-windowed sinc, cut off at half the Nyquist frequency
+        let mut h: [f64; TAPS] = core::array::from_fn(|j| {
+            let n = j as f64 - CENTER as f64;
+            let sinc = if n == 0.0 {1.0} else {(PI*n/2.0).sin()/(PI*n/2.0)};
+            let w = j as f64/(TAPS - 1) as f64;
+            let window = 0.42 - 0.5*(TAU*w).cos() + 0.08*(2.0*TAU*w).cos();
+            0.5*sinc*window
+        });
+
+        // Unity DC gain, keeping the center tap at one half so the odd phase stays a pure delay
+        let side: f64 = h.iter()
+            .enumerate()
+            .filter(|&(j, _)| j != CENTER)
+            .map(|(_, h)| h)
+            .sum();
+        for (j, h) in h.iter_mut()
+            .enumerate()
+        {
+            if j != CENTER
+            {
+                *h *= 0.5/side;
+            }
+        }
+
+        Self {
+            h,
+            stages: [(); _].map(|()| HalfBand::new()),
+            pad: [0.0; _],
+            pad_index: 0,
+            oversampling: Oversampling::Off
+        }
+    }
+
+    /// Runs `f` at the oversampled rate. `f` has to take the rate multiplied by the [Oversampling::factor] into account.
+    pub fn process(&mut self, oversampling: Oversampling, x: f64, mut f: impl FnMut(f64) -> f64) -> f64
+    {
+        if self.oversampling != oversampling
+        {
+            self.oversampling = oversampling;
+            self.reset();
+        }
+
+        let factor = oversampling.factor();
+        let pad = Self::LATENCY*factor - STAGE_DELAY*(factor - 1);
+        let Self {h, stages, pad: buffer, pad_index, ..} = self;
+        let mut f = |x: f64| {
+            // Pad the delay at the highest rate, so the total is a whole number of host samples
+            let y = if pad > 0
+            {
+                *pad_index %= pad;
+                let y = core::mem::replace(&mut buffer[*pad_index], x);
+                *pad_index += 1;
+                y
+            }
+            else
+            {
+                x
+            };
+            f(y)
+        };
+
+        Self::process_stages(h, &mut stages[..oversampling.stages()], x, &mut f)
+    }
+
+    fn process_stages(h: &[f64; TAPS], stages: &mut [HalfBand], x: f64, f: &mut impl FnMut(f64) -> f64) -> f64
+    {
+        match stages.split_first_mut()
+        {
+            Some((stage, rest)) => {
+                let x = stage.upsample(h, x)
+                    .map(|x| Self::process_stages(h, rest, x, f));
+                stage.downsample(h, x)
+            },
+            None => f(x)
+        }
+    }
+
+    pub fn reset(&mut self)
+    {
+        for stage in self.stages.iter_mut()
+        {
+            stage.reset();
+        }
+        self.pad = [0.0; _];
+        self.pad_index = 0;
+    }
+}
+
+impl Default for Oversampler
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
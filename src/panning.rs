@@ -0,0 +1,85 @@
+use core::f64::consts::FRAC_PI_2;
+
+use crate::{reverb::M, Layout};
+
+#[cfg(test)]
+#[test]
+fn equal_power()
+{
+    for layout in [Layout::Stereo, Layout::Mono, Layout::Quad, Layout::Surround51, Layout::Surround71]
+    {
+        let n = layout.outputs();
+        for spread in [0.0, 0.3, 1.0]
+        {
+            let mut taps = vec![[0.0; M]; n*n];
+            output_taps(layout, spread, &mut taps);
+            for &c in layout.speaker_order()
+            {
+                let row = &taps[c*n..(c + 1)*n];
+                for i in 0..M
+                {
+                    let power: f64 = row.iter()
+                        .map(|t| t[i].powi(2))
+                        .sum();
+                    assert!((power - 1.0).abs() < 1e-12, "{layout:?} {spread}");
+                }
+                if spread == 0.0
+                {
+                    assert_eq!(row[c], [1.0; M]);
+                }
+            }
+        }
+    }
+}
+
+/// Step used to scatter the lines across the field. Coprime with [M], so neighbouring (similar length) lines end up far apart.
+const SCATTER: usize = 13;
+
+/// Position of line `i` relative to its home speaker, between -1 and 1.
+fn line_offset(i: usize) -> f64
+{
+    (((i*SCATTER) % M) as f64 + 0.5)/M as f64*2.0 - 1.0
+}
+
+/// Fills the `n`×`n` row-major matrix of output tap vectors, where `taps[c*n + k]` holds the weights of each line of the reverb of channel `c` into
+/// output `k`.
+///
+/// With no `spread`, each reverb only feeds its own output. With full `spread` the lines of every reverb are scattered evenly across the whole field,
+/// equal-power panned between neighbouring speakers. Stereo fields are panned linearly from left to right, larger fields around the full speaker circle.
+pub fn output_taps(layout: Layout, spread: f64, taps: &mut [[f64; M]])
+{
+    let n = layout.outputs();
+    let order = layout.speaker_order();
+    let s = order.len();
+    let circular = s > 2;
+
+    for t in taps.iter_mut()
+    {
+        *t = [0.0; M];
+    }
+    for (h, &c) in order.iter()
+        .enumerate()
+    {
+        let row = &mut taps[c*n..(c + 1)*n];
+        for (i, d) in (0..M).map(line_offset)
+            .enumerate()
+        {
+            let p = if circular
+            {
+                (h as f64 + spread*d*s as f64/2.0).rem_euclid(s as f64)
+            }
+            else
+            {
+                let target = (d + 1.0)/2.0*(s - 1) as f64;
+                h as f64 + spread*(target - h as f64)
+            };
+
+            let a = p.floor() as usize % s;
+            let b = (a + 1) % s;
+            let frac = p - p.floor();
+            let [g_a, g_b] = [(frac*FRAC_PI_2).cos(), (frac*FRAC_PI_2).sin()];
+            row[order[a]][i] += g_a;
+            row[order[b]][i] += g_b;
+        }
+    }
+}
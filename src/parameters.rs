@@ -1,4 +1,4 @@
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
 
 use vst::prelude::PluginParameters;
 use vst::util::AtomicFloat;
@@ -13,134 +13,380 @@ pub const FREQUENCY_MAX: f32 = 20000.0;
 const PRIMES_MIN: f32 = 0.1;
 const PRIMES_MAX: f32 = 10.0;
 
-pub enum ReverbParam
+pub const BASS_MONO_MIN: f32 = FREQUENCY_MIN;
+pub const BASS_MONO_MAX: f32 = 1000.0;
+pub const WIDTH_MAX: f32 = 2.0;
+
+pub const Q_MIN: f32 = 0.1;
+pub const Q_MAX: f32 = 10.0;
+
+pub const DECAY_SCALE_MIN: f32 = 0.25;
+pub const DECAY_SCALE_MAX: f32 = 4.0;
+
+pub const DRIVE_MIN: f32 = 0.1;
+pub const DRIVE_MAX: f32 = 10.0;
+
+/// Range of the cached tube curves, in volts.
+pub const TUBE_RANGE_MIN: f32 = 5.0;
+pub const TUBE_RANGE_MAX: f32 = 80.0;
+/// Points cached across the range of the tube curves.
+pub const TUBE_RESOLUTION_MIN: f32 = 128.0;
+pub const TUBE_RESOLUTION_MAX: f32 = 8192.0;
+
+pub const TRANSFORMER_LOW_CUT_MAX: f32 = 1000.0;
+pub const TRANSFORMER_HIGH_CUT_MIN: f32 = 1000.0;
+pub const TRANSFORMER_HIGH_CUT_MAX: f32 = FREQUENCY_MAX;
+
+/// Longest parameter smoothing time, in seconds.
+pub const SMOOTHING_MAX: f32 = 0.5;
+
+/// Level knobs are linear in dB down to here, and mute at the very bottom.
+pub const LEVEL_MIN_DB: f32 = -60.0;
+/// Gain, wet and dry go as high as before they were calibrated in dB.
+pub const LEVEL_MAX_DB: f32 = 20.0;
+pub const SEND_MAX_DB: f32 = 0.0;
+pub const TRIM_DB: f32 = 24.0;
+
+#[cfg(test)]
+#[test]
+fn mix_lock()
 {
-    Gain,
-    Wet,
-    Dry,
-    Feedback,
-    StereoSeparation,
-    Floor,
-    Ceiling,
-    Prescence,
-    Mids,
-    Mud,
-    Primes,
-    Length,
-    Phase
+    let param = ReverbParameters::default();
+    param.mix.set(0.25);
+
+    // Browsing presets replaces the mix, until it's locked
+    param.store(ReverbBank::default());
+    assert_eq!(param.mix.get(), 0.5);
+
+    param.mix.set(0.25);
+    param.mix_lock.store(true, Ordering::Relaxed);
+    param.store(ReverbBank::default());
+    assert_eq!(param.mix.get(), 0.25);
+    assert!(param.mix_lock.load(Ordering::Relaxed));
 }
 
-impl ReverbParam
+#[cfg(test)]
+#[test]
+fn unity_dry()
 {
-    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
-
-    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
-        Self::Gain,
-        Self::Wet,
-        Self::Dry,
-        Self::Feedback,
-        Self::StereoSeparation,
-        Self::Floor,
-        Self::Ceiling,
-        Self::Prescence,
-        Self::Mids,
-        Self::Mud,
-        Self::Primes,
-        Self::Length,
-        Self::Phase
-    ];
+    let param = ReverbParameters::default();
+    assert_eq!(param.dry.get(), 1.0);
+    assert_eq!(param.get_parameter_text(ReverbParam::Dry as i32), "0.000");
+
+    // Going through the knob lands back on exactly unity
+    param.set_parameter(ReverbParam::Dry as i32, param.get_parameter(ReverbParam::Dry as i32));
+    assert_eq!(param.dry.get(), 1.0);
+
+    // Old banks load at the same level
+    assert_eq!(ReverbParameters::from(ReverbBank::default()).load().dry, ReverbBank::default().dry);
 }
 
-pub struct ReverbParameters
+#[cfg(test)]
+#[test]
+fn text_round_trip()
 {
-    pub gain: AtomicFloat,
-    pub wet: AtomicFloat,
-    pub dry: AtomicFloat,
-    pub feedback: AtomicFloat,
-    pub stereo_separation: AtomicFloat,
-    pub ceiling: AtomicFloat,
-    pub floor: AtomicFloat,
-    pub prescence: AtomicFloat,
-    pub mids: AtomicFloat,
-    pub mud: AtomicFloat,
-    pub primes: AtomicFloat,
-    pub length: AtomicFloat,
-    pub phase: AtomicU16,
+    let param = ReverbParameters::default();
+    param.oversampled.store(true, Ordering::Relaxed);
+    for p in ReverbParam::VARIANTS
+    {
+        let index = p as i32;
+        for value in [0.0, 0.1, 0.37, 0.5, 0.8, 1.0]
+        {
+            param.set_parameter(index, value);
+            let value = param.get_parameter(index);
+            let text = format!("{} {}", param.get_parameter_text(index), param.get_parameter_label(index));
+
+            param.set_parameter(index, 1.0 - value);
+            assert!(param.string_to_parameter(index, text.clone()), "{p:?}: couldn't parse \"{text}\"");
+            let parsed = param.get_parameter(index);
+            assert!((parsed - value).abs() < 1e-3, "{p:?}: \"{text}\" is {parsed}, expected {value}");
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn text_units()
+{
+    let param = ReverbParameters::default();
+
+    assert!(param.string_to_parameter(ReverbParam::Ceiling as i32, "2.5 kHz".to_string()));
+    assert!((param.ceiling.get() - 2500.0).abs() < 0.1);
+    assert!(param.string_to_parameter(ReverbParam::Dry as i32, "-6 dB".to_string()));
+    assert!((20.0*param.dry.get().log10() + 6.0).abs() < 1e-3);
+    assert!(param.string_to_parameter(ReverbParam::Wet as i32, "-6 dB".to_string()));
+    assert_eq!(param.get_parameter_label(ReverbParam::Wet as i32), "dBr");
+    assert!((20.0*param.wet.get().log10() + 6.0).abs() < 1e-3);
+    assert!(param.string_to_parameter(ReverbParam::Mix as i32, "35 %".to_string()));
+    assert!((param.mix.get() - 0.35).abs() < 1e-6);
+    assert!(param.string_to_parameter(ReverbParam::Smoothing as i32, "0.1 s".to_string()));
+    assert!((param.smoothing.get() - 0.1).abs() < 1e-6);
+    assert!(param.string_to_parameter(ReverbParam::Feedback as i32, "1.8 s".to_string()));
+    assert!((param.rt60(param.get_parameter(ReverbParam::Feedback as i32)) - 1.8).abs() < 1e-3);
+    assert!(param.string_to_parameter(ReverbParam::Length as i32, "250 ms".to_string()));
+    assert!((param.delays(param.get_parameter(ReverbParam::Length as i32))[0] - 0.25).abs() < 1e-4);
+    assert!(param.string_to_parameter(ReverbParam::TubeBias as i32, "crunch".to_string()));
+    assert_eq!(param.tube_bias.load(Ordering::Relaxed), TubeBias::Crunch as u8);
+
+    // Out of range clamps, and nonsense is refused
+    assert!(param.string_to_parameter(ReverbParam::Floor as i32, "1 Hz".to_string()));
+    assert!((param.floor.get() - FREQUENCY_MIN).abs() < 1e-3);
+    assert!(!param.string_to_parameter(ReverbParam::Floor as i32, "2.5 dB".to_string()));
+    assert!(!param.string_to_parameter(ReverbParam::Engine as i32, "Plate".to_string()));
+
+    // Without any delay, the tail is either gone at once or never decays, so no time can be set
+    param.length.set(0.0);
+    let feedback = param.feedback.get();
+    assert!(!param.string_to_parameter(ReverbParam::Feedback as i32, "2 s".to_string()));
+    assert_eq!(param.feedback.get(), feedback);
+}
+
+const EQ_CURVE: Scale = Scale::Curve {curve: LOG_CURVE as f32, max: EQ_MAX};
+const FREQUENCY: Scale = Scale::Log {min: FREQUENCY_MIN, max: FREQUENCY_MAX};
+const DECAY_SCALE: Scale = Scale::Log {min: DECAY_SCALE_MIN, max: DECAY_SCALE_MAX};
+const DRIVE: Scale = Scale::Log {min: DRIVE_MIN, max: DRIVE_MAX};
+const TRIM: Scale = Scale::Linear {min: -TRIM_DB, max: TRIM_DB};
+
+reverb_parameters! {
+    state {
+        /// Sample rate of the host, for showing delays and decay times.
+        rate: AtomicFloat = AtomicFloat::new(44100.0);
+        /// Whether the instance opted into oversampling, see [Oversampling::ENV].
+        oversampled: AtomicBool = AtomicBool::new(false);
+    }
+    // Gain and wet are relative to where they've always been, since their level through the tubes and the reverb depends on the other settings
+    Gain => gain: AtomicFloat as f64 {
+        name: "Gain", unit: Unit::RelativeGain, scale: Scale::Level {max_db: LEVEL_MAX_DB}, smoothed: true
+    } per LOG_MID = LOG_MID;
+    Wet => wet: AtomicFloat as f64 {
+        name: "Wet", unit: Unit::RelativeGain, scale: Scale::Level {max_db: LEVEL_MAX_DB}, smoothed: true
+    } per LOG_MID = LOG_MID;
+    Dry => dry: AtomicFloat as f64 {
+        name: "Dry", unit: Unit::Gain, scale: Scale::Level {max_db: LEVEL_MAX_DB}, smoothed: true
+    } per LOG_MID = LOG_MID;
+    Feedback => feedback: AtomicFloat as f64 {
+        name: "Feedback", unit: Unit::Percent, scale: Scale::Curve {curve: REVERB_CURVE, max: 1.0}, smoothed: true, derived: Some(Derived {
+            unit: "s",
+            quantity: |param, value| param.rt60(value),
+            text: |param, value| format!("{:.3}", param.rt60(value))
+        })
+    } = 0.5f64.powf(REVERB_CURVE as f64);
+    StereoSeparation => stereo_separation: AtomicFloat as f64 {
+        name: "Stereo Separation", unit: Unit::Percent, scale: Scale::Curve {curve: LOG_CURVE as f32, max: 1.0}, smoothed: true, applies: ReverbParameters::is_per_channel
+    } = LOG_MID;
+    Floor => floor: AtomicFloat as f64 {
+        name: "Floor", unit: Unit::Hertz, scale: FREQUENCY, smoothed: true
+    } = FREQUENCY_MIN as f64;
+    Ceiling => ceiling: AtomicFloat as f64 {
+        name: "Ceiling", unit: Unit::Hertz, scale: FREQUENCY, smoothed: true
+    } = FREQUENCY_MAX as f64;
+    Prescence => prescence: AtomicFloat as f64 {
+        name: "Prescence", unit: Unit::Percent, scale: EQ_CURVE, smoothed: true
+    } = EQ_MID;
+    Mids => mids: AtomicFloat as f64 {
+        name: "Mids", unit: Unit::Percent, scale: EQ_CURVE, smoothed: true
+    } = EQ_MID;
+    Mud => mud: AtomicFloat as f64 {
+        name: "Mud", unit: Unit::Percent, scale: EQ_CURVE, smoothed: true
+    } = EQ_MID;
+    // Unlike the other continuous parameters, these aren't smoothed. They pick whole prime line lengths, so they re-layout the delay lines in steps,
+    // and each step resamples the lines into new buffers.
+    Primes => primes: AtomicFloat as f64 {
+        name: "Primes", scale: Scale::Log {min: PRIMES_MIN, max: PRIMES_MAX}, derived: Some(Derived {
+            unit: "x",
+            quantity: |param, value| param.spread(value),
+            text: |param, value| format!("{:.3}", param.spread(value))
+        })
+    } = 1.0;
+    Length => length: AtomicFloat as f64 {
+        name: "Length", unit: Unit::Percent, scale: Scale::Curve {curve: LOG_CURVE as f32, max: 1.0}, derived: Some(Derived {
+            unit: "ms",
+            quantity: |param, value| 1000.0*param.delays(value)[0],
+            text: |param, value| {
+                let [longest, average] = param.delays(value);
+                format!("{:.3} / {:.3}", 1000.0*longest, 1000.0*average)
+            }
+        })
+    } = LOG_MID;
+    Phase => phase: AtomicU16 as u16 {
+        name: "Phase", scale: Scale::Steps {count: M*M}
+    } = 0;
+    Coupling => coupling: AtomicU8 as u8 {
+        name: "Coupling", unit: Unit::Names(|i| Coupling::from_index(i).name()), scale: Scale::Steps {count: Coupling::VARIANT_COUNT}, applies: ReverbParameters::is_per_channel
+    } = 0;
+    MidSide => mid_side: AtomicBool as bool {
+        name: "Mid/Side", unit: Unit::OnOff, scale: Scale::Switch
+    } = false;
+    MidSend => mid_send: AtomicFloat as f64 {
+        name: "Mid Send", unit: Unit::Gain, scale: Scale::Level {max_db: SEND_MAX_DB}, smoothed: true
+    } = 1.0;
+    SideSend => side_send: AtomicFloat as f64 {
+        name: "Side Send", unit: Unit::Gain, scale: Scale::Level {max_db: SEND_MAX_DB}, smoothed: true
+    } = 1.0;
+    MidDecay => mid_decay: AtomicFloat as f64 {
+        name: "Mid Decay", unit: Unit::Percent, scale: DECAY_SCALE, smoothed: true, applies: ReverbParameters::is_per_channel
+    } = 1.0;
+    SideDecay => side_decay: AtomicFloat as f64 {
+        name: "Side Decay", unit: Unit::Percent, scale: DECAY_SCALE, smoothed: true, applies: ReverbParameters::is_per_channel
+    } = 1.0;
+    Width => width: AtomicFloat as f64 {
+        name: "Width", unit: Unit::Percent, scale: Scale::Linear {min: 0.0, max: WIDTH_MAX}, smoothed: true
+    } = 1.0;
+    BassMono => bass_mono: AtomicFloat as f64 {
+        name: "Bass Mono", unit: Unit::Hertz, scale: Scale::Log {min: BASS_MONO_MIN, max: BASS_MONO_MAX}, off: Off::Bottom, smoothed: true
+    } = BASS_MONO_MIN as f64;
+    Spread => spread: AtomicFloat as f64 {
+        name: "Spread", unit: Unit::Percent, smoothed: true, applies: ReverbParameters::is_per_channel
+    } = 0.0;
+    InputDistribution => input_distribution: AtomicU8 as u8 {
+        name: "Input Distribution", unit: Unit::Names(|i| InputDistribution::from_index(i).name()), scale: Scale::Steps {count: InputDistribution::VARIANT_COUNT},
+        applies: ReverbParameters::is_per_channel
+    } = 0;
+    // Switching engines clears the tail, so it's not something to automate
+    Engine => engine: AtomicU8 as u8 {
+        name: "Engine", unit: Unit::Names(|i| Engine::from_index(i).name()), scale: Scale::Steps {count: Engine::VARIANT_COUNT}, automatable: false
+    } = 0;
+    BassFrequency => bass_frequency: AtomicFloat as f64 {
+        name: "Bass Frequency", unit: Unit::Hertz, scale: FREQUENCY, smoothed: true
+    } = BASS_F;
+    TrebleFrequency => treble_frequency: AtomicFloat as f64 {
+        name: "Treble Frequency", unit: Unit::Hertz, scale: FREQUENCY, smoothed: true
+    } = TREBLE_F;
+    MidFrequency => mid_frequency: AtomicFloat as f64 {
+        name: "Mid Frequency", unit: Unit::Hertz, scale: FREQUENCY, smoothed: true
+    } = MID_F;
+    MidQ => mid_q: AtomicFloat as f64 {
+        name: "Mid Q", scale: Scale::Log {min: Q_MIN, max: Q_MAX}, smoothed: true
+    } = MID_Q;
+    /// Missing in banks from before the peaking mid, which get [ToneMode::Crossover].
+    #[serde(default)]
+    ToneMode => tone_mode: AtomicU8 as Option<u8> {
+        name: "Tone Stack", unit: Unit::Names(|i| ToneMode::from_index(i).name()), scale: Scale::Steps {count: ToneMode::VARIANT_COUNT}
+    } = Some(ToneMode::BandSplit as u8);
+    TubeType => tube_type: AtomicU8 as u8 {
+        name: "Tube Type", unit: Unit::Names(|i| TubeType::from_index(i).name()), scale: Scale::Steps {count: TubeType::VARIANT_COUNT}
+    } = TubeType::Tube12AU7 as u8;
+    TubeBias => tube_bias: AtomicU8 as u8 {
+        name: "Tube Bias", unit: Unit::Names(|i| TubeBias::from_index(i).name()), scale: Scale::Steps {count: TubeBias::VARIANT_COUNT}
+    } = TubeBias::Warm as u8;
+    InputDrive => input_drive: AtomicFloat as f64 {
+        name: "Input Drive", unit: Unit::Gain, scale: DRIVE, smoothed: true
+    } = 1.0;
+    OutputDrive => output_drive: AtomicFloat as f64 {
+        name: "Output Drive", unit: Unit::Gain, scale: DRIVE, smoothed: true
+    } = 1.0;
+    Character => character: AtomicU8 as u8 {
+        name: "Character", unit: Unit::Names(|i| Character::from_index(i).name()), scale: Scale::Steps {count: Character::VARIANT_COUNT}
+    } = Character::TubeTransformer as u8;
+    Oversampling => oversampling: AtomicU8 as u8 {
+        name: "Oversampling", unit: Unit::Names(|i| Oversampling::from_index(i).name()), scale: Scale::Steps {count: Oversampling::VARIANT_COUNT},
+        applies: |param| param.oversampled.load(Ordering::Relaxed)
+    } = Oversampling::Off as u8;
+    TransformerLowCut => transformer_low_cut: AtomicFloat as f64 {
+        name: "Transformer Low Cut", unit: Unit::Hertz, scale: Scale::Log {min: FREQUENCY_MIN, max: TRANSFORMER_LOW_CUT_MAX}, smoothed: true
+    } = TRANSFORMER_LOW_CUT;
+    TransformerHighCut => transformer_high_cut: AtomicFloat as f64 {
+        name: "Transformer High Cut", unit: Unit::Hertz, scale: Scale::Log {min: TRANSFORMER_HIGH_CUT_MIN, max: TRANSFORMER_HIGH_CUT_MAX}, off: Off::Top, smoothed: true
+    } = TRANSFORMER_HIGH_CUT_MAX as f64;
+    TransformerSaturation => transformer_saturation: AtomicFloat as f64 {
+        name: "Transformer Saturation", unit: Unit::Percent, smoothed: true
+    } = 0.0;
+    DampingSlope => damping_slope: AtomicU8 as u8 {
+        name: "Damping Slope", unit: Unit::Names(|i| DampingSlope::from_index(i).name()), scale: Scale::Steps {count: DampingSlope::VARIANT_COUNT}
+    } = DampingSlope::Db6 as u8;
+    /// Missing in banks from before the mode existed, see [DampingMode::legacy].
+    #[serde(default)]
+    DampingMode => damping_mode: AtomicU8 as Option<u8> {
+        name: "Damping Mode", unit: Unit::Names(|i| DampingMode::from_index(i).name()), scale: Scale::Steps {count: DampingMode::VARIANT_COUNT}
+    } = Some(DampingMode::BandPass as u8);
+    Smoothing => smoothing: AtomicFloat as f64 {
+        name: "Smoothing", unit: Unit::Milliseconds, scale: Scale::Linear {min: 0.0, max: SMOOTHING_MAX}
+    } = 0.02;
+    Mix => mix: AtomicFloat as f64 {
+        name: "Mix", unit: Unit::Percent, smoothed: true
+    } = 0.5;
+    // Only matters while browsing presets
+    MixLock => mix_lock: AtomicBool as bool {
+        name: "Mix Lock", unit: Unit::OnOff, scale: Scale::Switch, automatable: false
+    } = false;
+    InputTrim => input_trim: AtomicFloat as f64 {
+        name: "Input Trim", unit: Unit::Decibel, scale: TRIM, smoothed: true
+    } = 0.0;
+    OutputTrim => output_trim: AtomicFloat as f64 {
+        name: "Output Trim", unit: Unit::Decibel, scale: TRIM, smoothed: true
+    } = 0.0;
+    // Changing the cache rebuilds the tubes off the audio thread, so changes land a little late
+    TubeRange => tube_range: AtomicFloat as f64 {
+        name: "Tube Range", unit: Unit::Volts, scale: Scale::Log {min: TUBE_RANGE_MIN, max: TUBE_RANGE_MAX}
+    } = TubeCache::DEFAULT.range;
+    TubeResolution => tube_resolution: AtomicFloat as f64 {
+        name: "Tube Resolution", scale: Scale::Log {min: TUBE_RESOLUTION_MIN, max: TUBE_RESOLUTION_MAX}
+    } = TubeCache::DEFAULT.resolution as f64;
 }
 
 impl ReverbParameters
 {
-    pub fn store(&self, bank: ReverbBank)
+    /// Coupling, stereo separation, the mid/side decays, spread and input distribution work with the reverbs of each channel, and don't apply to a
+    /// shared one.
+    fn is_per_channel(&self) -> bool
+    {
+        Engine::from_index(self.engine.load(Ordering::Relaxed)) == Engine::PerChannel
+    }
+
+    pub fn store(&self, mut bank: ReverbBank)
     {
-        let ReverbBank {
-            gain,
-            wet,
-            dry,
-            feedback,
-            stereo_separation,
-            ceiling,
-            floor,
-            prescence,
-            mids,
-            mud,
-            primes,
-            length,
-            phase
-        } = bank;
-        self.gain.set(gain as f32);
-        self.wet.set(wet as f32);
-        self.dry.set(dry as f32);
-        self.feedback.set(feedback as f32);
-        self.stereo_separation.set(stereo_separation as f32);
-        self.ceiling.set(ceiling as f32);
-        self.floor.set(floor as f32);
-        self.prescence.set(prescence as f32);
-        self.mids.set(mids as f32);
-        self.mud.set(mud as f32);
-        self.primes.set(primes as f32);
-        self.length.set(length as f32);
-        self.phase.store(phase, Ordering::Relaxed);
+        // The mix stays put while browsing presets with the lock on
+        if self.mix_lock.load(Ordering::Relaxed)
+        {
+            bank.mix = self.mix.get() as f64;
+            bank.mix_lock = true;
+        }
+        let params = ReverbParameters::from(bank);
+        for param in ReverbParam::VARIANTS
+        {
+            self.atomic(param).set_value(params.atomic(param).value());
+        }
     }
     pub fn load(&self) -> ReverbBank
     {
         self.into()
     }
-}
 
-impl From<ReverbBank> for ReverbParameters
-{
-    fn from(bank: ReverbBank) -> Self
+    /// Lengths of the delay lines in seconds, before they're rounded to primes, which is close enough to show.
+    fn line_lengths(&self, primes: f32, length: f32) -> [f64; M]
     {
-        let ReverbBank {
-            gain,
-            wet,
-            dry,
-            feedback,
-            stereo_separation,
-            ceiling,
-            floor,
-            prescence,
-            mids,
-            mud,
-            primes,
-            length,
-            phase
-        } = bank;
-        Self {
-            gain: AtomicFloat::new(gain as f32),
-            wet: AtomicFloat::new(wet as f32),
-            dry: AtomicFloat::new(dry as f32),
-            feedback: AtomicFloat::new(feedback as f32),
-            stereo_separation: AtomicFloat::new(stereo_separation as f32),
-            ceiling: AtomicFloat::new(ceiling as f32),
-            floor: AtomicFloat::new(floor as f32),
-            prescence: AtomicFloat::new(prescence as f32),
-            mids: AtomicFloat::new(mids as f32),
-            mud: AtomicFloat::new(mud as f32),
-            primes: AtomicFloat::new(primes as f32),
-            length: AtomicFloat::new(length as f32),
-            phase: AtomicU16::new(phase)
+        let rate = self.rate.get() as f64;
+        util::line_lengths::<M>(primes as f64, D as f64*length as f64).map(|n| n/rate)
+    }
+
+    /// Approximate time for the tail to decay by 60 dB, with the feedback knob at `value`. Leaves out the damping.
+    fn rt60(&self, value: f32) -> f64
+    {
+        let feedback = ReverbParam::Feedback.descriptor().scale.denormalize(value) as f64;
+        if feedback >= 1.0
+        {
+            return f64::INFINITY
         }
+        // Each trip around the loop goes through a line of average length, and scales the tail by the feedback
+        let lengths = self.line_lengths(self.primes.get(), self.length.get());
+        let average = lengths.iter().sum::<f64>()/M as f64;
+        3.0*average/-feedback.log10()
+    }
+
+    /// Longest and average delay in seconds, with the length knob at `value`.
+    fn delays(&self, value: f32) -> [f64; 2]
+    {
+        let length = ReverbParam::Length.descriptor().scale.denormalize(value);
+        let lengths = self.line_lengths(self.primes.get(), length);
+        [lengths.iter().copied().fold(0.0, f64::max), lengths.iter().sum::<f64>()/M as f64]
+    }
+
+    /// Ratio of the longest line to the shortest, with the primes knob at `value`. No line is shorter than a sample.
+    fn spread(&self, value: f32) -> f64
+    {
+        let primes = ReverbParam::Primes.descriptor().scale.denormalize(value);
+        let lengths = self.line_lengths(primes, self.length.get());
+        lengths[M - 1]/lengths[0].max(1.0/self.rate.get() as f64)
     }
 }
 
@@ -158,21 +404,14 @@ impl PluginParameters for ReverbParameters
     {
         match ReverbParam::VARIANTS.get(index as usize)
         {
-            Some(param) => match param
-            {
-                ReverbParam::Gain => "%",
-                ReverbParam::Wet => "%",
-                ReverbParam::Dry => "%",
-                ReverbParam::Feedback => "%",
-                ReverbParam::StereoSeparation => "%",
-                ReverbParam::Floor => "Hz",
-                ReverbParam::Ceiling => "Hz",
-                ReverbParam::Prescence => "%",
-                ReverbParam::Mids => "%",
-                ReverbParam::Mud => "%",
-                ReverbParam::Primes => "",
-                ReverbParam::Length => "%",
-                ReverbParam::Phase => ""
+            Some(&param) if !(param.descriptor().applies)(self) => "",
+            Some(&param) => {
+                let descriptor = param.descriptor();
+                match descriptor.derived
+                {
+                    Some(derived) => derived.unit,
+                    None => descriptor.label(self.atomic(param).value())
+                }
             },
             None => ""
         }.to_string()
@@ -182,22 +421,16 @@ impl PluginParameters for ReverbParameters
     {
         match ReverbParam::VARIANTS.get(index as usize)
         {
-            Some(param) => match param
-            {
-                ReverbParam::Gain => format!("{:.3}", 100.0*self.gain.get().powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Wet => format!("{:.3}", 100.0*self.wet.get().powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Dry => format!("{:.3}", 100.0*self.dry.get().powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Feedback => format!("{:.3}", 100.0*self.feedback.get().powf(1.0/REVERB_CURVE as f32)),
-                ReverbParam::StereoSeparation => format!("{:.3}", 100.0*self.stereo_separation.get().powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Floor => format!("{:.3}", self.floor.get()),
-                ReverbParam::Ceiling => format!("{:.3}", self.ceiling.get()),
-                ReverbParam::Prescence => format!("{:.3}", 100.0*(self.prescence.get()/EQ_MAX).powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Mids => format!("{:.3}", 100.0*(self.mids.get()/EQ_MAX).powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Mud => format!("{:.3}", 100.0*(self.mud.get()/EQ_MAX).powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Primes => format!("{:.3}", self.primes.get()),
-                ReverbParam::Length => format!("{:.3}", 100.0*self.length.get().powf(1.0/LOG_CURVE as f32)),
-                ReverbParam::Phase => format!("{}", self.phase.load(Ordering::Relaxed)),
-            }, 
+            Some(&param) if !(param.descriptor().applies)(self) => "Off".to_string(),
+            Some(&param) => {
+                let descriptor = param.descriptor();
+                let x = self.atomic(param).value();
+                match descriptor.derived
+                {
+                    Some(derived) => (derived.text)(self, descriptor.scale.normalize(x)),
+                    None => descriptor.text(x)
+                }
+            },
             None => "".to_string()
         }
     }
@@ -206,72 +439,25 @@ impl PluginParameters for ReverbParameters
     {
         match ReverbParam::VARIANTS.get(index as usize)
         {
-            Some(param) => match param
-            {
-                ReverbParam::Gain => "Gain",
-                ReverbParam::Wet => "Wet",
-                ReverbParam::Dry => "Dry",
-                ReverbParam::Feedback => "Feedback",
-                ReverbParam::StereoSeparation => "Stereo Separation",
-                ReverbParam::Floor => "Floor",
-                ReverbParam::Ceiling => "Ceiling",
-                ReverbParam::Prescence => "Prescence",
-                ReverbParam::Mids => "Mids",
-                ReverbParam::Mud => "Mud",
-                ReverbParam::Primes => "Primes",
-                ReverbParam::Length => "Length",
-                ReverbParam::Phase => "Phase"
-            },
+            Some(&param) => param.descriptor().name,
             None => ""
         }.to_string()
     }
 
-    /// Get the value of parameter at `index`. Should be value between 0.0 and 1.0.
     fn get_parameter(&self, index: i32) -> f32
     {
         match ReverbParam::VARIANTS.get(index as usize)
         {
-            Some(param) => match param
-            {
-                ReverbParam::Gain => self.gain.get().powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Wet => self.wet.get().powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Dry => self.dry.get().powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Feedback => self.feedback.get().powf(1.0/REVERB_CURVE as f32),
-                ReverbParam::StereoSeparation => self.stereo_separation.get().powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Floor => (self.floor.get().log2() - FREQUENCY_MIN.log2())/(FREQUENCY_MAX.log2() - FREQUENCY_MIN.log2()),
-                ReverbParam::Ceiling => (self.ceiling.get().log2() - FREQUENCY_MIN.log2())/(FREQUENCY_MAX.log2() - FREQUENCY_MIN.log2()),
-                ReverbParam::Prescence => (self.prescence.get()/EQ_MAX).powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Mids => (self.mids.get()/EQ_MAX).powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Mud => (self.mud.get()/EQ_MAX).powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Primes => (self.primes.get().log2() - PRIMES_MIN.log2())/(PRIMES_MAX.log2() - PRIMES_MIN.log2()),
-                ReverbParam::Length => self.length.get().powf(1.0/LOG_CURVE as f32),
-                ReverbParam::Phase => self.phase.load(Ordering::Relaxed) as f32/(M*M - 1) as f32
-            },
+            Some(&param) => param.descriptor().scale.normalize(self.atomic(param).value()),
             None => 0.0
         }
     }
     
     fn set_parameter(&self, index: i32, value: f32)
     {
-        match ReverbParam::VARIANTS.get(index as usize)
+        if let Some(&param) = ReverbParam::VARIANTS.get(index as usize)
         {
-            Some(param) => match param
-            {
-                ReverbParam::Gain => self.gain.set(value.powf(LOG_CURVE as f32)),
-                ReverbParam::Wet => self.wet.set(value.powf(LOG_CURVE as f32)),
-                ReverbParam::Dry => self.dry.set(value.powf(LOG_CURVE as f32)),
-                ReverbParam::Feedback => self.feedback.set(value.powf(REVERB_CURVE as f32)),
-                ReverbParam::StereoSeparation => self.stereo_separation.set(value.powf(LOG_CURVE as f32)),
-                ReverbParam::Floor => self.floor.set((value*(FREQUENCY_MAX.log2() - FREQUENCY_MIN.log2()) + FREQUENCY_MIN.log2()).exp2()),
-                ReverbParam::Ceiling => self.ceiling.set((value*(FREQUENCY_MAX.log2() - FREQUENCY_MIN.log2()) + FREQUENCY_MIN.log2()).exp2()),
-                ReverbParam::Prescence => self.prescence.set(value.powf(LOG_CURVE as f32)*EQ_MAX),
-                ReverbParam::Mids => self.mids.set(value.powf(LOG_CURVE as f32)*EQ_MAX),
-                ReverbParam::Mud => self.mud.set(value.powf(LOG_CURVE as f32)*EQ_MAX),
-                ReverbParam::Primes => self.primes.set((value*(PRIMES_MAX.log2() - PRIMES_MIN.log2()) + PRIMES_MIN.log2()).exp2()),
-                ReverbParam::Length => self.length.set(value.powf(LOG_CURVE as f32)),
-                ReverbParam::Phase => self.phase.store((value*(M*M - 1) as f32) as u16, Ordering::Relaxed)
-            },
-            None => ()
+            self.atomic(param).set_value(param.descriptor().scale.denormalize(value))
         }
     }
 
@@ -284,6 +470,26 @@ impl PluginParameters for ReverbParameters
 
     fn set_preset_name(&self, _name: String) {}
 
+    fn string_to_parameter(&self, index: i32, text: String) -> bool
+    {
+        match ReverbParam::VARIANTS.get(index as usize)
+            .and_then(|param| {
+                let descriptor = param.descriptor();
+                match descriptor.derived
+                {
+                    Some(derived) => derived.parse(self, &text),
+                    None => descriptor.parse(&text)
+                }
+            })
+        {
+            Some(value) => {
+                self.set_parameter(index, value);
+                true
+            },
+            None => false
+        }
+    }
+
     fn get_preset_name(&self, _preset: i32) -> String
     {
         "".to_string()
@@ -291,7 +497,8 @@ impl PluginParameters for ReverbParameters
 
     fn can_be_automated(&self, index: i32) -> bool
     {
-        (index as usize) < ReverbParam::VARIANT_COUNT
+        ReverbParam::VARIANTS.get(index as usize)
+            .is_some_and(|param| param.descriptor().automatable)
     }
 
     fn get_preset_data(&self) -> Vec<u8>
@@ -1,10 +1,9 @@
 use std::{f64::consts::TAU, sync::atomic::Ordering};
 
 use num::traits::MulAddAssign;
-use real_time_fir_iir_filters::{conf::{All, LowPass}, filters::iir::first::FirstOrderFilter, param::Omega, rtf::Rtf};
 use delay_line::DelayLine;
 
-use crate::{util, ReverbParameters};
+use crate::{util, Damping, DampingMode, DampingSlope, ReverbParameters, SILENCE_THRESHOLD};
 
 pub const M: usize = 32;
 pub const D: usize = 50000;
@@ -20,48 +19,204 @@ fn primes()
     println!("{:?}", util::primes::<M>(100, 3));
 }
 
+#[cfg(test)]
+#[test]
+fn tail_size()
+{
+    let rate = 44100.0;
+
+    for (feedback, length) in [(0.5, 0.01), (0.9, 0.01)]
+    {
+        let param = ReverbParameters::default();
+        param.feedback.set(feedback);
+        param.length.set(length);
+
+        let mut reverb = FDNReverb::new(0);
+        reverb.update(&param);
+        let tail = reverb.tail_size(rate);
+
+        let mut last = 0;
+        for n in 0..(tail*2.0) as usize
+        {
+            reverb.process1(rate);
+            let y: f64 = reverb.process2(&[if n == 0 {1.0} else {0.0}; M], &[0.0; M], 1.0).iter().sum();
+            if y.abs() > SILENCE_THRESHOLD
+            {
+                last = n;
+            }
+        }
+
+        println!("tail = {tail}, measured = {last}");
+        assert!(last as f64 <= tail && last as f64 > tail*0.5)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn denormal_decay()
+{
+    let rate = 44100.0;
+
+    // Short, fast decaying room, so the tail would reach the subnormal range well within the test. The floor is raised so its filters don't
+    // hold on to the tail.
+    let param = ReverbParameters::default();
+    param.feedback.set(0.1);
+    param.length.set(0.001);
+    param.floor.set(2000.0);
+
+    let mut reverb = FDNReverb::new(0);
+    reverb.update(&param);
+    let p_max = reverb.p.iter()
+        .copied()
+        .max()
+        .unwrap_or(0);
+
+    for n in 0..400*p_max
+    {
+        reverb.process1(rate);
+        reverb.process2(&[if n == 0 {1.0} else {0.0}; M], &[0.0; M], 1.0);
+        if n % p_max == 0
+        {
+            assert!(reverb.w.iter().flat_map(|w| w.iter()).chain(&reverb.z).chain(&reverb.y).all(|z| !z.is_subnormal()));
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn damping_slope()
+{
+    use crate::damping::magnitude;
+
+    let rate = 44100.0;
+
+    // Steeper slopes leave less treble in the tail
+    let mut last = f64::INFINITY;
+    for slope in DampingSlope::VARIANTS
+    {
+        let param = ReverbParameters::default();
+        param.feedback.set(0.9);
+        param.floor.set(20.0);
+        param.ceiling.set(1000.0);
+        param.damping_slope.store(slope as u8, Ordering::Relaxed);
+
+        let mut reverb = FDNReverb::new(0);
+        reverb.update(&param);
+
+        let y: Vec<f64> = (0..rate as usize).map(|n| {
+                reverb.process1(rate);
+                reverb.process2(&[if n == 0 {1.0} else {0.0}; M], &[0.0; M], 1.0).iter().sum()
+            })
+            .collect();
+        // Skip the direct pass through the lines, and window the rest so the bass doesn't leak into the treble
+        let p_max = reverb.p.iter()
+            .copied()
+            .max()
+            .unwrap_or(0);
+        let n_tail = y.len() - p_max;
+        let tail: Vec<f64> = y[p_max..].iter()
+            .enumerate()
+            .map(|(n, y)| y*(core::f64::consts::PI*n as f64/n_tail as f64).sin().powi(2))
+            .collect();
+        let tail = &tail[..];
+        let db = 20.0*(magnitude(rate, tail, 4000.0)/magnitude(rate, tail, 500.0)).log10();
+
+        println!("{}: {db:.2} dB", slope.name());
+        assert!(db < last - 6.0);
+        last = db;
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn decorrelation()
+{
+    const N: usize = 1 << 15;
+
+    let rate = 44100.0;
+    let param = ReverbParameters::default();
+    param.feedback.set(0.9);
+
+    let reverbs: Vec<FDNReverb> = (0..4).map(|c| {
+            let mut reverb = FDNReverb::new(c);
+            reverb.update(&param);
+            reverb
+        })
+        .collect();
+
+    // Half the signs differ between any two, and not just in the first few lines
+    for (a, b) in reverbs.iter()
+        .zip(reverbs.iter().skip(1))
+    {
+        let differ: Vec<usize> = (0..M).filter(|&i| a.g[i] != b.g[i])
+            .collect();
+        assert_eq!(differ.len(), M/2);
+        assert!(differ.iter().any(|&i| i >= M/2));
+    }
+
+    // So the same impulse comes out of each with little in common, once it's gone through the loop. The direct pass through the lines is the same.
+    let p_max = reverbs[0].p.iter()
+        .copied()
+        .max()
+        .unwrap_or(0);
+    let y: Vec<Vec<f64>> = reverbs.into_iter()
+        .map(|mut reverb| (0..N).map(|n| {
+                reverb.process1(rate);
+                reverb.process2(&[if n == 0 {1.0} else {0.0}; M], &[0.0; M], 1.0).iter().sum()
+            })
+            .skip(p_max)
+            .collect())
+        .collect();
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a*b).sum::<f64>();
+    for (a, b) in y.iter()
+        .zip(y.iter().skip(1))
+    {
+        let correlation = dot(a, b)/(dot(a, a)*dot(b, b)).sqrt();
+        println!("correlation = {correlation:.3}");
+        assert!(correlation.abs() < 0.1);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FDNReverb
 {
     w: [DelayLine<f64>; M],
-    f_f: [FirstOrderFilter<All, f64>; M],
-    f_c: [FirstOrderFilter<LowPass, f64>; M],
+    damping: [Damping; M],
     prime_curve: f64,
     length: f64,
     phase: u16,
+    /// Signs on top of [PHASES] that set decorrelated reverbs apart. Rows of a Hadamard matrix, so any two differ in half the lines, spread across all
+    /// of them.
+    signs: [f64; M],
     feedback: f64,
+    decay_scale: f64,
     p: [usize; M],
     g: [f64; M],
     q: [[f64; M]; M],
-    z: [f64; M]
+    z: [f64; M],
+    y: [f64; M],
+    noise: util::DenormalNoise
 }
 
 impl FDNReverb
 {
-    pub fn new() -> Self
+    pub fn new(decorrelation: usize) -> Self
     {
         Self {
             w: [(); _].map(|()| DelayLine::new()),
-            f_f: [
-                FirstOrderFilter::new(Omega {
-                    omega: TAU*220.0
-                });
-                _
-            ],
-            f_c: [
-                FirstOrderFilter::new(Omega {
-                    omega: TAU*220.0
-                });
-                _
-            ],
+            damping: [(); _].map(|()| Damping::new()),
             prime_curve: 0.0,
             length: 0.0,
             phase: 0,
+            signs: util::hadamard_matrix::<M>()[decorrelation % M].map(f64::signum),
             feedback: 0.0,
+            decay_scale: 1.0,
             p: [0; _],
             g: [1.0; _],
             q: util::hadamard_feedback_matrix(),
-            z: [0.0; _]
+            z: [0.0; _],
+            y: [0.0; _],
+            noise: util::DenormalNoise::new(0x5EED)
         }
     }
 
@@ -74,11 +229,7 @@ impl FDNReverb
         {
             self.phase = phase;
             self.feedback = feedback;
-            self.g = PHASES[self.phase as usize];
-            for g in self.g.iter_mut()
-            {
-                *g *= feedback;
-            }
+            self.update_gains();
         }
 
         // Update feedback matrix
@@ -92,20 +243,27 @@ impl FDNReverb
         // Update filters
         let floor = params.floor.get() as f64*TAU;
         let ceiling = params.ceiling.get() as f64*TAU;
-        if self.f_f[0].param.omega != floor
+        let slope = DampingSlope::from_index(params.damping_slope.load(Ordering::Relaxed));
+        if self.damping[0].floor() != floor
         {
-            for filter in self.f_f.iter_mut()
+            for damping in self.damping.iter_mut()
             {
-                filter.param.omega = floor;
+                damping.set_floor(floor);
             }
         }
-        if self.f_c[0].param.omega != ceiling
+        if self.damping[0].ceiling() != ceiling
         {
-            for filter in self.f_c.iter_mut()
+            for damping in self.damping.iter_mut()
             {
-                filter.param.omega = ceiling;
+                damping.set_ceiling(ceiling);
             }
         }
+        let mode = DampingMode::from_index(params.damping_mode.load(Ordering::Relaxed));
+        for damping in self.damping.iter_mut()
+        {
+            damping.set_slope(slope);
+            damping.set_mode(mode);
+        }
 
         // Update delay lines
         let prime_curve = params.primes.get() as f64;
@@ -124,30 +282,71 @@ impl FDNReverb
         }
     }
 
+    /// Scales the decay time of the loop, on top of the feedback parameter.
+    pub fn set_decay_scale(&mut self, decay_scale: f64)
+    {
+        if self.decay_scale != decay_scale
+        {
+            self.decay_scale = decay_scale;
+            self.update_gains();
+        }
+    }
+
+    fn update_gains(&mut self)
+    {
+        // Decay time is inversely proportional to the log of the loop gain
+        let feedback = self.feedback.powf(1.0/self.decay_scale);
+        self.g = PHASES[self.phase as usize % (M*M)];
+        for (g, sign) in self.g.iter_mut()
+            .zip(self.signs)
+        {
+            *g *= sign*feedback;
+        }
+    }
+
+    /// Number of samples it takes for the loop to decay below [SILENCE_THRESHOLD], given the current gains, delays and damping.
+    ///
+    /// The feedback matrix is orthogonal, so each pass through the delay lines scales the energy by the loop gain at most. The floor filters ring out on
+    /// their own time constant, which dominates short, dark tails. Infinite if the loop doesn't decay at all.
+    pub fn tail_size(&self, rate: f64) -> f64
+    {
+        let damping = self.damping[0].peak_gain();
+
+        let loop_gain = self.g.iter()
+            .map(|g| g.abs())
+            .fold(0.0, f64::max)*damping;
+        if loop_gain >= 1.0
+        {
+            return f64::INFINITY
+        }
+        // All lines are summed at the output, so allow for M times the headroom
+        let passes = (SILENCE_THRESHOLD/M as f64).ln()/loop_gain.ln();
+
+        let p_max = self.p.iter()
+            .copied()
+            .max()
+            .unwrap_or(0) as f64;
+        let p_mean = self.p.iter()
+            .sum::<usize>() as f64/M as f64;
+
+        // Each section of the cascade adds its own ring-out
+        let ring = rate/self.damping[0].floor()*(M as f64/SILENCE_THRESHOLD).ln()*self.damping[0].slope().order() as f64;
+
+        p_max + (passes*p_mean).max(ring)
+    }
+
     pub fn process1(&mut self, rate: f64) -> &[f64; M]
     {
-        for ((z, w), (f_f, f_c)) in self.z.iter_mut()
+        for ((z, w), damping) in self.z.iter_mut()
             .zip(self.w.iter())
-            .zip(self.f_f.iter_mut()
-                .zip(self.f_c.iter_mut())
-            )
+            .zip(self.damping.iter_mut())
         {
             *z = w.output()
                 .copied()
                 .unwrap_or(0.0);
 
             // Apply feedback
-            let b = f_f.param.omega > f_c.param.omega;
-            let z_h;
-            [*z, z_h] = {
-                let zz = f_f.filter(rate, *z);
-                [zz[!b as usize], zz[b as usize]]
-            };
-            [*z] = f_c.filter(rate, *z);
-            if b
-            {
-                *z += z_h
-            }
+            *z = damping.process(rate, *z);
         }
 
         util::rmul_matrix_assign_row(&self.q, &mut self.z);
@@ -155,22 +354,26 @@ impl FDNReverb
         &self.z
     }
 
-    pub fn process2(&mut self, x: f64, z_avg: &[f64; M], stereo_separation: f64) -> f64
+    /// Takes the input of each line, already weighted by the input vectors. Returns the output of each line, so they can be tapped into the output
+    /// channels.
+    pub fn process2(&mut self, x: &[f64; M], z_avg: &[f64; M], stereo_separation: f64) -> &[f64; M]
     {
-        let mut y = 0.0;
+        let noise = self.noise.next();
 
-        for (((z, &z_avg), &g), w) in self.z.iter_mut()
+        for (((((z, &z_avg), &g), w), y), &x) in self.z.iter_mut()
             .zip(z_avg)
             .zip(self.g.iter())
             .zip(self.w.iter_mut())
+            .zip(self.y.iter_mut())
+            .zip(x)
         {
             z.mul_add_assign(stereo_separation, z_avg);
-            z.mul_add_assign(g, x);
+            z.mul_add_assign(g, x + noise);
             
-            y += w.delay(*z);
+            *y = w.delay(*z);
         }
 
-        y
+        &self.y
     }
 
     pub fn suspend(&mut self)
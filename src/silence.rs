@@ -0,0 +1,78 @@
+/// Anything below this is considered silence (-120 dBFS).
+pub const SILENCE_THRESHOLD: f64 = 1e-6;
+
+#[cfg(test)]
+#[test]
+fn sleep_and_wake()
+{
+    const TAIL: usize = 1000;
+
+    let mut silence = SilenceDetector::default();
+    assert!(!silence.input(0.0));
+
+    // Sleeps only once it's been silent for longer than the tail
+    assert!(!silence.output(100, 1.0, TAIL));
+    for _ in 0..TAIL/100
+    {
+        assert!(!silence.output(100, 0.0, TAIL));
+    }
+    assert!(silence.output(100, 0.0, TAIL));
+    assert!(silence.sleeping());
+    assert!(silence.input(SILENCE_THRESHOLD));
+
+    // And wakes on the first input above the threshold
+    assert!(!silence.input(SILENCE_THRESHOLD*2.0));
+    assert!(!silence.sleeping());
+}
+
+/// Tracks input and tail energy, so processing can be skipped once the tail has died.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilenceDetector
+{
+    silent_for: usize,
+    sleeping: bool
+}
+
+impl SilenceDetector
+{
+    /// Registers the peak of the incoming block. Returns `true` if the block can be skipped.
+    ///
+    /// Any input above the threshold wakes the engine up immediately, so processing resumes on the very block the input comes back.
+    pub fn input(&mut self, peak: f64) -> bool
+    {
+        if peak > SILENCE_THRESHOLD
+        {
+            self.silent_for = 0;
+            self.sleeping = false;
+        }
+        self.sleeping
+    }
+
+    /// Registers the mean square of a processed block of `len` samples. Returns `true` if the engine just fell asleep.
+    pub fn output(&mut self, len: usize, energy: f64, tail: usize) -> bool
+    {
+        if self.sleeping
+        {
+            return false
+        }
+        if energy > SILENCE_THRESHOLD*SILENCE_THRESHOLD
+        {
+            self.silent_for = 0;
+            return false
+        }
+        self.silent_for += len;
+        self.sleeping = self.silent_for > tail;
+        self.sleeping
+    }
+
+    #[cfg(test)]
+    pub fn sleeping(&self) -> bool
+    {
+        self.sleeping
+    }
+
+    pub fn reset(&mut self)
+    {
+        *self = Self::default()
+    }
+}
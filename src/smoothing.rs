@@ -0,0 +1,172 @@
+use vst::prelude::PluginParameters;
+
+use crate::{ReverbParam, ReverbParameters};
+
+/// Close enough to the target to snap to it, in normalized parameter units.
+const SETTLED: f64 = 1e-5;
+/// Samples between updates of the filters and delay lines while parameters glide. Gains follow every sample.
+pub const UPDATE_INTERVAL: usize = 32;
+
+#[cfg(test)]
+#[test]
+fn continuous()
+{
+    // Stepped parameters would land between their steps
+    use crate::descriptor::Scale;
+
+    for param in ReverbParam::VARIANTS.into_iter().filter(|param| param.descriptor().smoothed)
+    {
+        assert!(!matches!(param.descriptor().scale, Scale::Steps {..} | Scale::Switch), "{param:?}");
+    }
+
+    // Every other continuous parameter glides, except those that re-layout the delay lines, rebuild the tubes, or set the glide itself
+    let unsmoothed = [ReverbParam::Primes, ReverbParam::Length, ReverbParam::TubeRange, ReverbParam::TubeResolution, ReverbParam::Smoothing];
+    for param in ReverbParam::VARIANTS.into_iter().filter(|param| !param.descriptor().smoothed)
+    {
+        assert!(matches!(param.descriptor().scale, Scale::Steps {..} | Scale::Switch) || unsmoothed.contains(&param), "{param:?}");
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn glide()
+{
+    let rate = 44100.0;
+
+    let param = ReverbParameters::default();
+    param.smoothing.set(0.01);
+    let mut smoother = ParameterSmoother::new();
+    smoother.block(&param);
+    assert!(!smoother.next(rate));
+
+    // Jump from silence to full gain
+    param.gain.set(0.0);
+    smoother.reset(&param);
+    param.gain.set(1.0);
+    smoother.block(&param);
+
+    let mut last = 0.0;
+    let mut settled = None;
+    for n in 0..rate as usize
+    {
+        if !smoother.next(rate)
+        {
+            settled = Some(n);
+            break
+        }
+        let gain = smoother.params().gain.get();
+        assert!(gain >= last && gain - last < 0.01);
+        last = gain;
+    }
+
+    // One-pole, so it settles within a handful of time constants
+    let settled = settled.unwrap();
+    println!("settled after {settled} samples");
+    assert!(settled as f64 > rate*0.01 && (settled as f64) < rate*0.01*15.0);
+    assert_eq!(smoother.params().gain.get(), 1.0);
+}
+
+/// Sits between [ReverbParameters] and the DSP, so automation glides instead of jumping once per block.
+///
+/// Continuous parameters are smoothed in their normalized domain, so frequencies glide logarithmically and levels follow their curves.
+pub struct ParameterSmoother
+{
+    params: ReverbParameters,
+    /// Parameters marked as [smoothed](crate::descriptor::ParamDescriptor::smoothed). The rest are discrete, or re-layout the delay lines.
+    smoothed: Vec<ReverbParam>,
+    target: Vec<f64>,
+    state: Vec<f64>,
+    time: f64
+}
+
+impl ParameterSmoother
+{
+    pub fn new() -> Self
+    {
+        let params = ReverbParameters::default();
+        let smoothed: Vec<_> = ReverbParam::VARIANTS.into_iter()
+            .filter(|param| param.descriptor().smoothed)
+            .collect();
+        let state: Vec<_> = smoothed.iter()
+            .map(|&param| params.get_parameter(param as i32) as f64)
+            .collect();
+        Self {
+            time: params.smoothing.get() as f64,
+            params,
+            smoothed,
+            target: state.clone(),
+            state
+        }
+    }
+
+    /// The smoothed parameters, to hand to the DSP.
+    pub fn params(&self) -> &ReverbParameters
+    {
+        &self.params
+    }
+
+    /// Takes new targets from the host's parameters. Discrete and block-rate parameters are copied as is.
+    pub fn block(&mut self, params: &ReverbParameters)
+    {
+        self.params.store(params.load());
+        self.time = params.smoothing.get() as f64;
+        for ((&param, target), &state) in self.smoothed.iter()
+            .zip(self.target.iter_mut())
+            .zip(self.state.iter())
+        {
+            *target = params.get_parameter(param as i32) as f64;
+            self.params.set_parameter(param as i32, state as f32);
+        }
+    }
+
+    /// Jumps straight to the host's parameters.
+    pub fn reset(&mut self, params: &ReverbParameters)
+    {
+        self.block(params);
+        self.settle();
+    }
+
+    /// Jumps straight to the targets taken at the last [block](ParameterSmoother::block).
+    pub fn settle(&mut self)
+    {
+        self.state.copy_from_slice(&self.target);
+        for (&param, &state) in self.smoothed.iter()
+            .zip(self.state.iter())
+        {
+            self.params.set_parameter(param as i32, state as f32);
+        }
+    }
+
+    /// Steps every parameter that hasn't reached its target yet. Returns whether any of them moved.
+    pub fn next(&mut self, rate: f64) -> bool
+    {
+        // One-pole, with `time` as its time constant
+        let k = if self.time > 0.0 {1.0 - (-1.0/(self.time*rate)).exp()} else {1.0};
+
+        let mut moved = false;
+        for ((&param, &target), state) in self.smoothed.iter()
+            .zip(self.target.iter())
+            .zip(self.state.iter_mut())
+        {
+            if *state != target
+            {
+                *state += (target - *state)*k;
+                if (target - *state).abs() < SETTLED
+                {
+                    *state = target;
+                }
+                self.params.set_parameter(param as i32, *state as f32);
+                moved = true;
+            }
+        }
+        moved
+    }
+}
+
+impl Default for ParameterSmoother
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
@@ -0,0 +1,252 @@
+use num::complex::Complex64;
+
+#[cfg(test)]
+#[test]
+fn tmb_response()
+{
+    let rate = 44100.0;
+
+    // Steady-state amplitude of a sine through the tone stack
+    let amplitude = |components, frequency: f64, pots| {
+        let mut tmb = TmbToneStack::new(components);
+        let n = rate as usize;
+        (0..n).map(|i| tmb.filter(rate, (core::f64::consts::TAU*frequency*i as f64/rate).sin(), pots))
+            .skip(n/2)
+            .fold(0.0, |a: f64, y| a.max(y.abs()))
+    };
+
+    for components in [ToneStackComponents::FENDER, ToneStackComponents::MARSHALL]
+    {
+        let center = amplitude(components, TmbToneStack::MAKEUP_FREQUENCY, [0.5; 3]);
+        println!("center = {center}");
+        assert!((center - 1.0).abs() < 0.05);
+
+        let bass_low = amplitude(components, 100.0, [0.5, 0.5, 0.0]);
+        let bass_high = amplitude(components, 100.0, [0.5, 0.5, 1.0]);
+        println!("bass = {bass_low}..{bass_high}");
+        assert!(bass_high > bass_low*2.0);
+    }
+}
+
+/// How the input of the channel is equalized before going into the reverb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMode
+{
+    /// Mud, mids and prescence weight the bands of a crossover, with a peaking mid.
+    BandSplit,
+    /// Passive bass-mid-treble network of a '59 Fender Bassman.
+    Fender,
+    /// Passive bass-mid-treble network of a Marshall JCM800.
+    Marshall,
+    /// Mud, mids and prescence weight the bands of a crossover, without the peaking mid. How banks from before the peaking mid sound.
+    Crossover
+}
+
+impl ToneMode
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::BandSplit,
+        Self::Fender,
+        Self::Marshall,
+        Self::Crossover
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::BandSplit)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::BandSplit => "Band Split",
+            Self::Fender => "Fender",
+            Self::Marshall => "Marshall",
+            Self::Crossover => "Crossover"
+        }
+    }
+
+    pub fn components(self) -> Option<ToneStackComponents>
+    {
+        match self
+        {
+            Self::BandSplit | Self::Crossover => None,
+            Self::Fender => Some(ToneStackComponents::FENDER),
+            Self::Marshall => Some(ToneStackComponents::MARSHALL)
+        }
+    }
+}
+
+/// Component values of a bass-mid-treble tone stack. `r1`, `r2` and `r3` are the treble, bass and mid pots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneStackComponents
+{
+    pub c1: f64,
+    pub c2: f64,
+    pub c3: f64,
+    pub r1: f64,
+    pub r2: f64,
+    pub r3: f64,
+    pub r4: f64
+}
+
+impl ToneStackComponents
+{
+    pub const FENDER: Self = Self {
+        c1: 250e-12,
+        c2: 20e-9,
+        c3: 20e-9,
+        r1: 250e3,
+        r2: 1e6,
+        r3: 25e3,
+        r4: 56e3
+    };
+    pub const MARSHALL: Self = Self {
+        c1: 470e-12,
+        c2: 22e-9,
+        c3: 22e-9,
+        r1: 220e3,
+        r2: 1e6,
+        r3: 22e3,
+        r4: 33e3
+    };
+
+    /// Coefficients of the analog transfer function, in ascending powers of `s`, for treble, mid and bass pot positions `t`, `m` and `l`.
+    ///
+    /// From D. T. Yeh and J. O. Smith, "Discretization of the '59 Fender Bassman tone stack", DAFx 2006.
+    fn analog(&self, [t, m, l]: [f64; 3]) -> ([f64; 4], [f64; 4])
+    {
+        let Self {c1, c2, c3, r1, r2, r3, r4} = *self;
+
+        let b1 = t*c1*r1 + m*c3*r3 + l*(c1*r2 + c2*r2) + (c1*r3 + c2*r3);
+        let b2 = t*(c1*c2*r1*r4 + c1*c3*r1*r4)
+            - m*m*(c1*c3*r3*r3 + c2*c3*r3*r3)
+            + m*(c1*c3*r1*r3 + c1*c3*r3*r3 + c2*c3*r3*r3)
+            + l*(c1*c2*r1*r2 + c1*c2*r2*r4 + c1*c3*r2*r4)
+            + l*m*(c1*c3*r2*r3 + c2*c3*r2*r3)
+            + (c1*c2*r1*r3 + c1*c2*r3*r4 + c1*c3*r3*r4);
+        let b3 = l*m*(c1*c2*c3*r1*r2*r3 + c1*c2*c3*r2*r3*r4)
+            - m*m*(c1*c2*c3*r1*r3*r3 + c1*c2*c3*r3*r3*r4)
+            + m*(c1*c2*c3*r1*r3*r3 + c1*c2*c3*r3*r3*r4)
+            + t*c1*c2*c3*r1*r3*r4
+            - t*m*c1*c2*c3*r1*r3*r4
+            + t*l*c1*c2*c3*r1*r2*r4;
+
+        let a0 = 1.0;
+        let a1 = (c1*r1 + c1*r3 + c2*r3 + c2*r4 + c3*r4) + m*c3*r3 + l*(c1*r2 + c2*r2);
+        let a2 = m*(c1*c3*r1*r3 - c2*c3*r3*r4 + c1*c3*r3*r3 + c2*c3*r3*r3)
+            + l*m*(c1*c3*r2*r3 + c2*c3*r2*r3)
+            - m*m*(c1*c3*r3*r3 + c2*c3*r3*r3)
+            + l*(c1*c2*r2*r4 + c1*c2*r1*r2 + c1*c3*r2*r4 + c2*c3*r2*r4)
+            + (c1*c2*r1*r4 + c1*c3*r1*r4 + c1*c2*r3*r4 + c1*c2*r1*r3 + c1*c3*r3*r4 + c2*c3*r3*r4);
+        let a3 = l*m*(c1*c2*c3*r1*r2*r3 + c1*c2*c3*r2*r3*r4)
+            - m*m*(c1*c2*c3*r1*r3*r3 + c1*c2*c3*r3*r3*r4)
+            + m*(c1*c2*c3*r3*r3*r4 + c1*c2*c3*r1*r3*r3 - c1*c2*c3*r1*r3*r4)
+            + l*c1*c2*c3*r1*r2*r4
+            + c1*c2*c3*r1*r3*r4;
+
+        ([0.0, b1, b2, b3], [a0, a1, a2, a3])
+    }
+}
+
+/// Audio taper of the bass pot.
+fn log_taper(x: f64) -> f64
+{
+    const TAPER: f64 = 100.0;
+    (TAPER.powf(x) - 1.0)/(TAPER - 1.0)
+}
+
+/// Circuit-modelled bass-mid-treble tone stack, discretized with the bilinear transform.
+#[derive(Debug, Clone)]
+pub struct TmbToneStack
+{
+    components: ToneStackComponents,
+    pots: [f64; 3],
+    rate: f64,
+    makeup: f64,
+    b: [f64; 4],
+    a: [f64; 4],
+    s: [f64; 3]
+}
+
+impl TmbToneStack
+{
+    /// Frequency at which the loss of the network is made up for, with all knobs centered.
+    const MAKEUP_FREQUENCY: f64 = 1000.0;
+
+    pub fn new(components: ToneStackComponents) -> Self
+    {
+        let (b, a) = components.analog([0.5, 0.5, log_taper(0.5)]);
+        let s = Complex64::new(0.0, core::f64::consts::TAU*Self::MAKEUP_FREQUENCY);
+        let eval = |p: [f64; 4]| p.into_iter()
+            .rev()
+            .fold(Complex64::new(0.0, 0.0), |y, p| y*s + p);
+        let makeup = (eval(a)/eval(b)).norm();
+
+        Self {
+            components,
+            pots: [f64::NAN; 3],
+            rate: f64::NAN,
+            makeup,
+            b: [0.0; 4],
+            a: [1.0, 0.0, 0.0, 0.0],
+            s: [0.0; 3]
+        }
+    }
+
+    pub fn components(&self) -> ToneStackComponents
+    {
+        self.components
+    }
+
+    /// Filters `x` with treble, mid and bass knobs between 0 and 1.
+    pub fn filter(&mut self, rate: f64, x: f64, pots: [f64; 3]) -> f64
+    {
+        if self.rate != rate || self.pots != pots
+        {
+            self.rate = rate;
+            self.pots = pots;
+            self.discretize();
+        }
+
+        // Transposed direct form II
+        let y = self.b[0]*x + self.s[0];
+        self.s[0] = self.b[1]*x - self.a[1]*y + self.s[1];
+        self.s[1] = self.b[2]*x - self.a[2]*y + self.s[2];
+        self.s[2] = self.b[3]*x - self.a[3]*y;
+
+        y*self.makeup
+    }
+
+    fn discretize(&mut self)
+    {
+        let [t, m, l] = self.pots;
+        let (b, a) = self.components.analog([t, m, log_taper(l)]);
+
+        let c = 2.0*self.rate;
+        let bilinear = |[p0, p1, p2, p3]: [f64; 4]| {
+            let [p1, p2, p3] = [p1*c, p2*c*c, p3*c*c*c];
+            [
+                p0 + p1 + p2 + p3,
+                3.0*p0 + p1 - p2 - 3.0*p3,
+                3.0*p0 - p1 - p2 + 3.0*p3,
+                p0 - p1 + p2 - p3
+            ]
+        };
+        let b = bilinear(b);
+        let a = bilinear(a);
+
+        self.b = b.map(|b| b/a[0]);
+        self.a = a.map(|an| an/a[0]);
+    }
+
+    pub fn reset(&mut self)
+    {
+        self.s = [0.0; 3];
+    }
+}
@@ -0,0 +1,173 @@
+use core::f64::consts::TAU;
+
+use real_time_fir_iir_filters::{conf::{HighPass, LowPass}, filters::iir::first::FirstOrderFilter, param::Omega, rtf::Rtf};
+
+use crate::{ReverbParameters, TRANSFORMER_HIGH_CUT_MAX};
+
+pub const TRANSFORMER_LOW_CUT: f64 = 20.0;
+/// Below this the flux in the core builds up, and is what saturates.
+const F_CORE: f64 = 100.0;
+/// How hard the core is driven at full saturation.
+const CORE_DRIVE: f64 = 4.0;
+
+/// Impulse response of a transformer set up from `param`.
+#[cfg(test)]
+fn impulse_response(rate: f64, param: &ReverbParameters) -> Vec<f64>
+{
+    const N: usize = 1 << 16;
+
+    let mut transformer = Transformer::new();
+    transformer.update(param);
+    (0..N).map(|n| transformer.process(rate, if n == 0 {1.0} else {0.0}))
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn low_cut()
+{
+    use crate::magnitude;
+
+    let rate = 44100.0;
+    let param = ReverbParameters::default();
+    for frequency in [20.0, 80.0, 200.0]
+    {
+        param.transformer_low_cut.set(frequency as f32);
+        let h = impulse_response(rate, &param);
+
+        // 3 dB down at the cut, flat well above it, and falling at 6 dB/oct well below it
+        assert!((magnitude(rate, &h, frequency) - core::f64::consts::FRAC_1_SQRT_2).abs() < 0.01, "{frequency} Hz");
+        assert!((magnitude(rate, &h, frequency*20.0) - 1.0).abs() < 0.01, "{frequency} Hz");
+        assert!((20.0*(magnitude(rate, &h, frequency/8.0)/magnitude(rate, &h, frequency/16.0)).log10() - 6.0).abs() < 0.5, "{frequency} Hz");
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn high_cut()
+{
+    use crate::magnitude;
+
+    let rate = 44100.0;
+    let param = ReverbParameters::default();
+
+    // Bypassed all the way up
+    let h = impulse_response(rate, &param);
+    assert!((magnitude(rate, &h, 15000.0) - 1.0).abs() < 0.01);
+
+    for frequency in [2000.0, 5000.0]
+    {
+        param.transformer_high_cut.set(frequency as f32);
+        let h = impulse_response(rate, &param);
+
+        // 3 dB down at the cut, and flat well below it
+        assert!((magnitude(rate, &h, frequency) - core::f64::consts::FRAC_1_SQRT_2).abs() < 0.02, "{frequency} Hz");
+        assert!((magnitude(rate, &h, frequency/10.0) - 1.0).abs() < 0.01, "{frequency} Hz");
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn saturation()
+{
+    use crate::magnitude;
+
+    const N: usize = 1 << 15;
+
+    let rate = 44100.0;
+    let param = ReverbParameters::default();
+
+    // Gain of a sine at `frequency` and `amplitude` through the transformer, after it has settled
+    let gain = |saturation: f32, frequency: f64, amplitude: f64| {
+        param.transformer_saturation.set(saturation);
+        let mut transformer = Transformer::new();
+        transformer.update(&param);
+        let y: Vec<f64> = (0..N).map(|n| transformer.process(rate, amplitude*(TAU*frequency*n as f64/rate).sin()))
+            .skip(N/2)
+            .collect();
+        magnitude(rate, &y, frequency)*2.0/y.len() as f64/amplitude
+    };
+
+    // Linear without saturation, whatever the level
+    assert!((gain(0.0, 50.0, 1.0)/gain(0.0, 50.0, 1e-3) - 1.0).abs() < 1e-6);
+    // Loud lows get squashed, quiet ones and highs pass as they are
+    assert!(gain(1.0, 50.0, 1.0) < 0.8*gain(0.0, 50.0, 1.0));
+    assert!((gain(1.0, 50.0, 1e-3)/gain(0.0, 50.0, 1e-3) - 1.0).abs() < 0.01);
+    assert!((gain(1.0, 5000.0, 1.0)/gain(0.0, 5000.0, 1.0) - 1.0).abs() < 0.02);
+}
+
+/// Coupling transformer. Cuts lows and gently rolls off highs, and its core saturates on low frequencies.
+#[derive(Debug, Clone)]
+pub struct Transformer
+{
+    low_cut: FirstOrderFilter<HighPass, f64>,
+    high_cut: FirstOrderFilter<LowPass, f64>,
+    core: FirstOrderFilter<LowPass, f64>,
+    high_cut_enabled: bool,
+    saturation: f64
+}
+
+impl Transformer
+{
+    pub fn new() -> Self
+    {
+        Self {
+            low_cut: FirstOrderFilter::new(Omega {
+                omega: TAU*TRANSFORMER_LOW_CUT
+            }),
+            high_cut: FirstOrderFilter::new(Omega {
+                omega: TAU*TRANSFORMER_HIGH_CUT_MAX as f64
+            }),
+            core: FirstOrderFilter::new(Omega {
+                omega: TAU*F_CORE
+            }),
+            high_cut_enabled: false,
+            saturation: 0.0
+        }
+    }
+
+    /// The highest high cut bypasses the roll-off, and zero saturation bypasses the core.
+    pub fn update(&mut self, params: &ReverbParameters)
+    {
+        self.low_cut.param.omega = TAU*params.transformer_low_cut.get() as f64;
+        let high_cut = params.transformer_high_cut.get();
+        self.high_cut_enabled = high_cut < TRANSFORMER_HIGH_CUT_MAX;
+        self.high_cut.param.omega = TAU*high_cut as f64;
+        self.saturation = params.transformer_saturation.get() as f64;
+    }
+
+    pub fn process(&mut self, rate: f64, x: f64) -> f64
+    {
+        let [mut y] = self.low_cut.filter(rate, x);
+
+        if self.saturation > 0.0
+        {
+            // Only the low end builds up enough flux to saturate the core, so distort that and leave the rest alone
+            let drive = self.saturation*CORE_DRIVE;
+            let [flux] = self.core.filter(rate, y);
+            y += (flux*drive).tanh()/drive - flux;
+        }
+
+        if self.high_cut_enabled
+        {
+            [y] = self.high_cut.filter(rate, y);
+        }
+
+        y
+    }
+
+    pub fn reset(&mut self)
+    {
+        self.low_cut.reset();
+        self.high_cut.reset();
+        self.core.reset();
+    }
+}
+
+impl Default for Transformer
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
@@ -0,0 +1,440 @@
+use std::{f64::consts::TAU, sync::{atomic::Ordering, mpsc::{self, Receiver, SyncSender}}, thread};
+
+use real_time_fir_iir_filters::{conf::LowPass, filters::iir::first::FirstOrderRCFilter, param::RC};
+use saturation::{tubes::{Tube12AU7, Tube12AX7, Tube6550, Tube6DJ8, Tube6L6CG, TubeKT88}, Triode, TriodeClassA};
+
+use crate::ReverbParameters;
+
+#[cfg(test)]
+#[test]
+fn cache_edges()
+{
+    const STEP: f64 = 1e-4;
+
+    for cache in [TubeCache::DEFAULT, TubeCache::DEFAULT.scaled(0.25)]
+    {
+        let edge = cache.edge();
+        let x: Vec<f64> = (0..((edge*4.0/STEP) as usize)).map(|n| n as f64*STEP - edge*2.0)
+            .collect();
+        let y: Vec<f64> = x.iter()
+            .map(|&x| cache.clamp(x))
+            .collect();
+
+        // Stays inside the cache, without jumps in the curve or its slope
+        assert!(y.iter().all(|y| y.abs() < edge));
+        for [y0, y1, y2] in y.array_windows()
+        {
+            assert!((y1 - y0).abs() <= STEP*(1.0 + 1e-9));
+            assert!((y2 - 2.0*y1 + y0).abs() < STEP*STEP*10.0/(edge - cache.range));
+        }
+
+        // Untouched within the range
+        assert!(x.iter().zip(y.iter()).filter(|(x, _)| x.abs() <= cache.range).all(|(x, y)| x == y));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn builder()
+{
+    use std::time::{Duration, Instant};
+
+    let mut builder = TubeBuilder::new(TubeSettings::DEFAULT, |settings| settings);
+    builder.request(TubeSettings::DEFAULT, 2);
+    assert!(builder.take().is_none());
+
+    // Keeps asking for the latest settings until they arrive, like the audio thread does every block
+    let settings = TubeSettings {
+        tube_type: TubeType::TubeKT88,
+        tube_bias: TubeBias::Crunch,
+        cache: TubeCache::DEFAULT.scaled(0.5)
+    };
+    let start = Instant::now();
+    let built = loop
+    {
+        builder.request(settings, 2);
+        if let Some(built) = builder.take()
+        {
+            break built
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(built, [settings; 2]);
+    builder.recycle(built);
+
+    builder.request(settings, 2);
+    thread::sleep(Duration::from_millis(10));
+    assert!(builder.take().is_none());
+}
+
+/// Range and resolution of the cached transfer curve of a tube stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TubeCache
+{
+    /// Input voltages between `-range` and `range` go through the tube as they are.
+    pub range: f64,
+    /// Points across the range. The margin beyond it is cached just as finely.
+    pub resolution: usize
+}
+
+impl TubeCache
+{
+    pub const DEFAULT: Self = Self {
+        range: 20.0,
+        resolution: 1024
+    };
+
+    /// How much further than the range the curve is cached, as a fraction of the range. Inputs beyond the range are bent into this margin.
+    const MARGIN: f64 = 0.25;
+
+    /// Cache covering `factor` times the range, at the same resolution per volt.
+    pub fn scaled(self, factor: f64) -> Self
+    {
+        Self {
+            range: self.range*factor,
+            resolution: (self.resolution as f64*factor).ceil() as usize
+        }
+    }
+
+    /// Outer edge of the cached curve.
+    pub fn edge(&self) -> f64
+    {
+        self.range*(1.0 + Self::MARGIN)
+    }
+
+    /// Points across the whole cached curve, margin included.
+    pub fn points(&self) -> usize
+    {
+        (self.resolution as f64*(1.0 + Self::MARGIN)).ceil() as usize
+    }
+
+    /// Soft clamp keeping `x` inside the cached curve. Leaves the range as it is, and beyond it bends inputs towards the edge, continuous in
+    /// value and slope. Never quite reaches the edge.
+    pub fn clamp(&self, x: f64) -> f64
+    {
+        if x.abs() <= self.range
+        {
+            return x
+        }
+        let soft = self.edge() - self.range;
+        (self.range + soft*((x.abs() - self.range)/soft).tanh()).copysign(x)
+    }
+}
+
+/// Which triode the input and output stages of each channel are modelled after. The 6L6CG, 6550 and KT88 are power tubes, wired as triodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TubeType
+{
+    Tube12AX7,
+    Tube6DJ8,
+    Tube12AU7,
+    Tube6L6CG,
+    Tube6550,
+    TubeKT88
+}
+
+impl TubeType
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Tube12AX7,
+        Self::Tube6DJ8,
+        Self::Tube12AU7,
+        Self::Tube6L6CG,
+        Self::Tube6550,
+        Self::TubeKT88
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::Tube12AU7)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Tube12AX7 => "12AX7",
+            Self::Tube6DJ8 => "6DJ8",
+            Self::Tube12AU7 => "12AU7",
+            Self::Tube6L6CG => "6L6CG",
+            Self::Tube6550 => "6550",
+            Self::TubeKT88 => "KT88"
+        }
+    }
+}
+
+/// Presets for the plate and bias voltages of the tube stages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TubeBias
+{
+    /// High plate voltage, lots of headroom.
+    Clean,
+    /// The original operating points.
+    Warm,
+    /// Starved plate and a hot bias, breaks up early.
+    Crunch
+}
+
+impl TubeBias
+{
+    pub const VARIANT_COUNT: usize = core::mem::variant_count::<Self>();
+
+    pub const VARIANTS: [Self; Self::VARIANT_COUNT] = [
+        Self::Clean,
+        Self::Warm,
+        Self::Crunch
+    ];
+
+    pub fn from_index(index: u8) -> Self
+    {
+        Self::VARIANTS.get(index as usize)
+            .copied()
+            .unwrap_or(Self::Warm)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Self::Clean => "Clean",
+            Self::Warm => "Warm",
+            Self::Crunch => "Crunch"
+        }
+    }
+
+    /// Moves the operating point of a stage. Returns the stage as is for [TubeBias::Warm].
+    pub fn operating_point(self, stage: TriodeClassA<f64>) -> TriodeClassA<f64>
+    {
+        let (v_pp, v_c) = match self
+        {
+            Self::Clean => (1.5, 0.8),
+            Self::Warm => (1.0, 1.0),
+            Self::Crunch => (0.6, 1.4)
+        };
+        TriodeClassA {
+            v_pp: stage.v_pp*v_pp,
+            v_c: stage.v_c*v_c,
+            ..stage
+        }
+    }
+}
+
+/// The default input filter of a [Triode], a low-pass from its input resistance.
+pub type InputFilter = FirstOrderRCFilter<LowPass, f64>;
+
+#[derive(Debug, Clone)]
+enum Model<FI>
+{
+    Tube12AX7(Triode<f64, Tube12AX7, FI>),
+    Tube6DJ8(Triode<f64, Tube6DJ8, FI>),
+    Tube12AU7(Triode<f64, Tube12AU7, FI>),
+    Tube6L6CG(Triode<f64, Tube6L6CG, FI>),
+    Tube6550(Triode<f64, Tube6550, FI>),
+    TubeKT88(Triode<f64, TubeKT88, FI>)
+}
+
+/// A triode stage with its tube chosen at runtime. `FI` is the input filter of the triode, `()` for none.
+#[derive(Debug, Clone)]
+pub struct TubeStage<FI = InputFilter>
+{
+    model: Model<FI>,
+    cache: TubeCache,
+    gain: f64
+}
+
+/// The small-signal gain of a stage is measured with a quiet sine at this frequency, in Hz, well inside the passband.
+const GAIN_FREQUENCY: f64 = 1000.0;
+/// ...at this sample rate.
+const GAIN_RATE: f64 = 48000.0;
+
+// The filter traits of `saturation` aren't exported, so each input filter gets its own impl.
+macro_rules! impl_tube_stage {
+    ($($fi:ty),*) => {
+        $(
+            impl TubeStage<$fi>
+            {
+                pub fn new(tube_type: TubeType, stage: TriodeClassA<f64>, rc: RC<f64>, cache: TubeCache) -> Self
+                {
+                    let (range, points) = (-cache.edge()..cache.edge(), cache.points());
+                    let mut stage = Self {
+                        model: match tube_type
+                        {
+                            TubeType::Tube12AX7 => Model::Tube12AX7(Triode::new(stage.cache(range, points), Default::default(), rc)),
+                            TubeType::Tube6DJ8 => Model::Tube6DJ8(Triode::new(stage.cache(range, points), Default::default(), rc)),
+                            TubeType::Tube12AU7 => Model::Tube12AU7(Triode::new(stage.cache(range, points), Default::default(), rc)),
+                            TubeType::Tube6L6CG => Model::Tube6L6CG(Triode::new(stage.cache(range, points), Default::default(), rc)),
+                            TubeType::Tube6550 => Model::Tube6550(Triode::new(stage.cache(range, points), Default::default(), rc)),
+                            TubeType::TubeKT88 => Model::TubeKT88(Triode::new(stage.cache(range, points), Default::default(), rc))
+                        },
+                        cache,
+                        gain: 1.0
+                    };
+                    stage.gain = stage.clone().measure_gain();
+                    stage
+                }
+
+                /// Projects the output onto the input after the filters have settled, so the inversion of the stage shows up as a negative gain.
+                fn measure_gain(&mut self) -> f64
+                {
+                    const PERIODS: usize = 40;
+
+                    let amplitude = self.cache.range*1e-3;
+                    let len = (GAIN_RATE/GAIN_FREQUENCY) as usize*PERIODS;
+                    let (mut xy, mut xx) = (0.0, 0.0);
+                    for n in 0..len
+                    {
+                        let x = amplitude*(TAU*GAIN_FREQUENCY*n as f64/GAIN_RATE).sin();
+                        let y = self.saturate(GAIN_RATE, x);
+                        if n >= len/2
+                        {
+                            xy += x*y;
+                            xx += x*x;
+                        }
+                    }
+                    xy/xx
+                }
+
+                /// Small-signal gain of the stage in its passband, negative since it inverts. Lets linear stand-ins for the tube keep its level
+                /// and polarity.
+                pub fn gain(&self) -> f64
+                {
+                    self.gain
+                }
+
+                /// Inputs beyond the cached curve are soft clamped into it, instead of falling off its edge.
+                pub fn saturate(&mut self, rate: f64, x: f64) -> f64
+                {
+                    let x = self.cache.clamp(x);
+                    match &mut self.model
+                    {
+                        Model::Tube12AX7(tube) => tube.saturate(rate, x),
+                        Model::Tube6DJ8(tube) => tube.saturate(rate, x),
+                        Model::Tube12AU7(tube) => tube.saturate(rate, x),
+                        Model::Tube6L6CG(tube) => tube.saturate(rate, x),
+                        Model::Tube6550(tube) => tube.saturate(rate, x),
+                        Model::TubeKT88(tube) => tube.saturate(rate, x)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_tube_stage!((), InputFilter);
+
+/// Everything the tube stages are built from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TubeSettings
+{
+    pub tube_type: TubeType,
+    pub tube_bias: TubeBias,
+    pub cache: TubeCache
+}
+
+impl TubeSettings
+{
+    pub const DEFAULT: Self = Self {
+        tube_type: TubeType::Tube12AU7,
+        tube_bias: TubeBias::Warm,
+        cache: TubeCache::DEFAULT
+    };
+
+    pub fn from_params(params: &ReverbParameters) -> Self
+    {
+        Self {
+            tube_type: TubeType::from_index(params.tube_type.load(Ordering::Relaxed)),
+            tube_bias: TubeBias::from_index(params.tube_bias.load(Ordering::Relaxed)),
+            cache: TubeCache {
+                range: params.tube_range.get() as f64,
+                resolution: params.tube_resolution.get().round() as usize
+            }
+        }
+    }
+}
+
+enum TubeJob<T>
+{
+    Build(TubeSettings, usize),
+    Drop(Vec<T>)
+}
+
+/// Builds tube stages on a worker thread, since caching their curves allocates and takes a while. None of its methods block or allocate, so they're
+/// safe on the audio thread.
+///
+/// Finished stages are [taken](TubeBuilder::take) and swapped in, and the stages they replace go back through [recycle](TubeBuilder::recycle), so
+/// they're freed on the worker as well.
+pub struct TubeBuilder<T>
+{
+    jobs: SyncSender<TubeJob<T>>,
+    built: Receiver<(TubeSettings, Vec<T>)>,
+    current: TubeSettings,
+    busy: bool
+}
+
+impl<T> TubeBuilder<T>
+where
+    T: Send + 'static
+{
+    /// Room for a build and the leftovers of a couple of swaps.
+    const JOBS: usize = 4;
+
+    /// `current` is what the stages in use were built with.
+    pub fn new(current: TubeSettings, build: fn(TubeSettings) -> T) -> Self
+    {
+        let (jobs, queue) = mpsc::sync_channel(Self::JOBS);
+        let (done, built) = mpsc::sync_channel(1);
+        thread::Builder::new()
+            .name("tube builder".to_string())
+            .spawn(move || {
+                for job in queue
+                {
+                    match job
+                    {
+                        TubeJob::Build(settings, count) => if done.send((settings, (0..count).map(|_| build(settings)).collect())).is_err()
+                        {
+                            break
+                        },
+                        TubeJob::Drop(stages) => drop(stages)
+                    }
+                }
+            })
+            .expect("Couldn't start the tube builder.");
+        Self {
+            jobs,
+            built,
+            current,
+            busy: false
+        }
+    }
+
+    /// Asks for `count` stages built with `settings`, unless that's what's in use already. Only one build runs at a time, so while the worker is busy,
+    /// call again later with the latest settings.
+    pub fn request(&mut self, settings: TubeSettings, count: usize)
+    {
+        if !self.busy && settings != self.current && self.jobs.try_send(TubeJob::Build(settings, count)).is_ok()
+        {
+            self.busy = true;
+        }
+    }
+
+    /// Finished stages, if there are any. They're counted as in use from here on.
+    pub fn take(&mut self) -> Option<Vec<T>>
+    {
+        let (settings, stages) = self.built.try_recv().ok()?;
+        self.current = settings;
+        self.busy = false;
+        Some(stages)
+    }
+
+    /// Hands the stages that were swapped out back to the worker to be freed.
+    pub fn recycle(&self, stages: Vec<T>)
+    {
+        // Only freed here if the queue is somehow full
+        let _ = self.jobs.try_send(TubeJob::Drop(stages));
+    }
+}
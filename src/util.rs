@@ -1,4 +1,103 @@
-use std::f64::consts::FRAC_1_SQRT_2;
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2};
+
+/// Amplitude of the noise injected into recursive paths. Roughly -360 dB, so it's inaudible, but keeps filter states far away from subnormal floats.
+pub const ANTI_DENORMAL: f64 = 1e-18;
+
+/// Cheap white noise generator (xorshift) used for denormal protection.
+#[derive(Debug, Clone, Copy)]
+pub struct DenormalNoise
+{
+    seed: u32
+}
+
+impl DenormalNoise
+{
+    pub const fn new(seed: u32) -> Self
+    {
+        Self {
+            seed: if seed == 0 {0x9E3779B9} else {seed}
+        }
+    }
+
+    pub fn next(&mut self) -> f64
+    {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f64/u32::MAX as f64*2.0 - 1.0)*ANTI_DENORMAL
+    }
+}
+
+#[cfg(all(test, any(target_arch = "x86", target_arch = "x86_64")))]
+#[test]
+fn denormal_guard()
+{
+    use core::hint::black_box;
+
+    let subnormal = || black_box(f64::MIN_POSITIVE)*black_box(0.5);
+    assert!(subnormal().is_subnormal());
+    {
+        let _guard = DenormalGuard::enable();
+        assert_eq!(subnormal(), 0.0);
+    }
+    assert!(subnormal().is_subnormal());
+}
+
+/// Enables flush-to-zero and denormals-are-zero for as long as the guard lives, and restores the previous state when dropped.
+///
+/// Only has an effect on x86, other architectures rely on [DenormalNoise] alone.
+pub struct DenormalGuard
+{
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    mxcsr: u32
+}
+
+impl DenormalGuard
+{
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    const FTZ_DAZ: u32 = 0x8040;
+
+    #[allow(deprecated)]
+    pub fn enable() -> Self
+    {
+        #[cfg(target_arch = "x86")]
+        use core::arch::x86::{_mm_getcsr, _mm_setcsr};
+        #[cfg(target_arch = "x86_64")]
+        use core::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            let mxcsr = unsafe {
+                _mm_getcsr()
+            };
+            unsafe {
+                _mm_setcsr(mxcsr | Self::FTZ_DAZ)
+            };
+            Self {
+                mxcsr
+            }
+        }
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        Self {}
+    }
+}
+
+impl Drop for DenormalGuard
+{
+    #[allow(deprecated)]
+    fn drop(&mut self)
+    {
+        #[cfg(target_arch = "x86")]
+        use core::arch::x86::_mm_setcsr;
+        #[cfg(target_arch = "x86_64")]
+        use core::arch::x86_64::_mm_setcsr;
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        unsafe {
+            _mm_setcsr(self.mxcsr)
+        }
+    }
+}
 
 pub const fn phases<const N: usize>() -> [[f64; N]; N*N]
 {
@@ -132,6 +231,37 @@ fn test()
     println!("{:?}", p.mul_matrix(&h))*/
 }
 
+#[cfg(test)]
+#[test]
+fn crossfade_ends()
+{
+    use std::f64::consts::SQRT_2;
+
+    assert_eq!(crossfade(0.5), [1.0, 1.0]);
+    // +3 dB at either end, as the other side fades out
+    for (mix, end) in [(0.0, [SQRT_2, 0.0]), (1.0, [0.0, SQRT_2])]
+    {
+        for (g, end) in crossfade(mix).into_iter()
+            .zip(end)
+        {
+            assert!((g - end).abs() < 1e-15, "{mix}");
+        }
+    }
+    for mix in [0.1, 0.3, 0.7, 0.9]
+    {
+        let [dry, wet] = crossfade(mix);
+        assert!((dry*dry + wet*wet - 2.0).abs() < 1e-12);
+    }
+}
+
+/// Equal-power `[dry, wet]` gains of the mix, scaled to unity at the center. Each end is then +3 dB, with the other side silent.
+pub fn crossfade(mix: f64) -> [f64; 2]
+{
+    // Same as √2·[cos, sin] of mix·π/2, but rotated around the center so it lands on exactly unity there
+    let (s, c) = ((mix - 0.5)*FRAC_PI_2).sin_cos();
+    [c - s, c + s]
+}
+
 pub const fn is_prime(n: usize) -> bool
 {
     let n_sqrt = 1 << ((n.ilog2() + 1) / 2);
@@ -177,17 +307,21 @@ pub const fn closest_prime(x: f64) -> usize
 pub fn primes_dist<const N: usize>(curve: f64, max: f64) -> [usize; N]
 where
     [(); N - 2]:
+{
+    line_lengths(curve, max).map(closest_prime)
+}
+
+/// Lengths of the lines before they're rounded to primes, spread out by `curve` and scaled so that their norm is `max`.
+pub fn line_lengths<const N: usize>(curve: f64, max: f64) -> [f64; N]
 {
     let mut scale = 0.0;
-    let x = core::array::from_fn(|i| {
+    let x: [f64; N] = core::array::from_fn(|i| {
         let x = ((i + 1) as f64/N as f64).powf(curve);
         scale += x*x;
         x
     });
     scale = max/scale.sqrt();
-    x.map(|x| {
-        closest_prime(x*scale)
-    })
+    x.map(|x| x*scale)
 }
 
 #[cfg(test)]
@@ -0,0 +1,52 @@
+use core::f64::consts::TAU;
+
+use real_time_fir_iir_filters::{conf::HighPass, filters::iir::first::FirstOrderFilter, param::Omega, rtf::Rtf};
+
+use crate::BASS_MONO_MIN;
+
+/// Stereo width and bass-mono stage for the wet signal.
+#[derive(Debug, Clone)]
+pub struct StereoWidth
+{
+    bass_mono: FirstOrderFilter<HighPass, f64>
+}
+
+impl StereoWidth
+{
+    pub fn new() -> Self
+    {
+        Self {
+            bass_mono: FirstOrderFilter::new(Omega {
+                omega: TAU*BASS_MONO_MIN as f64
+            })
+        }
+    }
+
+    /// Scales the side signal by `width`, and collapses everything below `bass_mono` (in Hz) to mono. The lowest setting bypasses bass-mono.
+    pub fn process(&mut self, rate: f64, [l, r]: [f64; 2], width: f64, bass_mono: f64) -> [f64; 2]
+    {
+        let m = (l + r)*0.5;
+        let mut s = (l - r)*0.5*width;
+
+        if bass_mono > BASS_MONO_MIN as f64
+        {
+            self.bass_mono.param.omega = TAU*bass_mono;
+            [s] = self.bass_mono.filter(rate, s);
+        }
+
+        [m + s, m - s]
+    }
+
+    pub fn suspend(&mut self)
+    {
+        self.bass_mono.reset();
+    }
+}
+
+impl Default for StereoWidth
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}